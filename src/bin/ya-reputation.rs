@@ -0,0 +1,154 @@
+//! Command line tool for managing blacklist of `BlacklistReputation` plugin
+//! without running provider agent and without editing blacklist file by hand.
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use ya_client_model::NodeId;
+use ya_local_reputation::blacklist::{
+    parse_node_id, BlacklistEntry, BlacklistReason, BlacklistReputationsConfig,
+};
+use ya_local_reputation::simulation::simulate;
+use ya_local_reputation::storage::{
+    Backend, FileStore, Format, OnParseError, ReputationStore, StateFiles, StateLock,
+};
+
+#[derive(StructOpt)]
+struct Args {
+    /// Working directory of plugin.
+    #[structopt(long, parse(from_os_str))]
+    workdir: PathBuf,
+    /// Negotiator name, if plugin instances are namespaced.
+    #[structopt(long, default_value = "")]
+    name: String,
+    #[structopt(long, default_value = "yaml")]
+    format: Format,
+    /// Location of blacklist file, if overridden in plugin config.
+    #[structopt(long, parse(from_os_str))]
+    blacklist_path: Option<PathBuf>,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Lists blacklisted Nodes.
+    List,
+    /// Adds Node to blacklist.
+    Add {
+        #[structopt(parse(try_from_str = parse_node_id))]
+        node: NodeId,
+        #[structopt(long, default_value = "added with CLI")]
+        reason: String,
+    },
+    /// Removes Node from blacklist.
+    Remove {
+        #[structopt(parse(try_from_str = parse_node_id))]
+        node: NodeId,
+    },
+    /// Removes all Nodes from blacklist.
+    Clear,
+    /// Removes payment statistics and strikes of all Nodes. Blacklist is kept.
+    ResetStats,
+    /// Attaches note to Node. Note is removed, if no text is given.
+    Note {
+        #[structopt(parse(try_from_str = parse_node_id))]
+        node: NodeId,
+        text: Option<String>,
+    },
+    /// Replays Agreement outcomes recorded with `record_outcomes` using candidate
+    /// config and shows how many Nodes it would blacklist.
+    Simulate {
+        /// Candidate plugin config in YAML or JSON.
+        #[structopt(parse(from_os_str))]
+        config: PathBuf,
+    },
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::from_args();
+
+    let files = StateFiles::new(args.workdir, args.format)
+        .with_instance(&args.name)
+        .with_path("blacklist", args.blacklist_path.as_deref());
+
+    // Running plugin instance holds the lock and would overwrite our changes.
+    let _lock = StateLock::acquire(&files.lock_path())?;
+
+    let store = FileStore::new(files, Backend::File, OnParseError::Fail);
+    let mut entries = store.read_blacklist()?;
+
+    match args.command {
+        Command::List => {
+            let notes = store.read_notes()?;
+            for entry in &entries {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    entry.node,
+                    entry.added.to_rfc3339(),
+                    entry.agreement_id.as_deref().unwrap_or("-"),
+                    entry.reason,
+                    notes.get(&entry.node).map(String::as_str).unwrap_or("")
+                );
+            }
+        }
+        Command::Add { node, reason } => {
+            if entries.iter().any(|entry| entry.node == node) {
+                println!("Node [{}] is already blacklisted.", node);
+                return Ok(());
+            }
+            entries.push(BlacklistEntry::new(node, BlacklistReason::Manual(reason)));
+            store.write_blacklist(&entries)?;
+            println!("Node [{}] added to blacklist.", node);
+        }
+        Command::Remove { node } => {
+            let before = entries.len();
+            entries.retain(|entry| entry.node != node);
+            if entries.len() == before {
+                anyhow::bail!("Node [{}] is not blacklisted.", node);
+            }
+            store.write_blacklist(&entries)?;
+            println!("Node [{}] removed from blacklist.", node);
+        }
+        Command::Clear => {
+            store.write_blacklist(&[])?;
+            println!("Removed {} Nodes from blacklist.", entries.len());
+        }
+        Command::ResetStats => {
+            store.reset_stats()?;
+            println!("Statistics of all Nodes removed.");
+        }
+        Command::Note { node, text } => {
+            let mut notes = store.read_notes()?;
+            match text {
+                Some(text) => {
+                    notes.insert(node, text);
+                    println!("Note attached to Node [{}].", node);
+                }
+                None => {
+                    if notes.remove(&node).is_none() {
+                        anyhow::bail!("Node [{}] has no note.", node);
+                    }
+                    println!("Note removed from Node [{}].", node);
+                }
+            }
+            store.write_notes(&notes)?;
+        }
+        Command::Simulate { config } => {
+            let config: BlacklistReputationsConfig =
+                serde_yaml::from_str(&std::fs::read_to_string(&config)?)?;
+            let history = store.load_history()?;
+            let report = simulate(&history, &config);
+
+            println!("Replayed agreements: {}", report.agreements);
+            for (reason, count) in &report.strikes {
+                println!("Strikes\t{}\t{}", reason, count);
+            }
+            for (reason, count) in &report.blacklisted {
+                println!("Blacklisted\t{}\t{}", reason, count);
+            }
+            println!("Redeemed nodes: {}", report.redeemed);
+            println!("Blacklisted nodes: {}", report.total_blacklisted());
+        }
+    }
+    Ok(())
+}
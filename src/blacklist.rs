@@ -1,12 +1,28 @@
 use chrono::{DateTime, Utc};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
 use structopt::StructOpt;
-use tokio::runtime::Runtime;
-use tokio::time::Instant;
+use tokio::runtime::{Handle, Runtime};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use url::Url;
+use uuid::Uuid;
+
+use crate::clock::{Clock, SystemClock};
+use crate::decay::{current_factor, DecayConfig};
+use crate::logging::{self, LogFormat, LogTarget, Rotation};
+use crate::metrics::Metrics;
+use crate::remote::RemoteBlacklist;
+use crate::simulation::AgreementOutcome;
+use crate::storage::{
+    lock, Backend, FileStore, Format, OnParseError, ReputationSnapshot, ReputationStore,
+    StateBackend, StateFiles, StateLock, SNAPSHOT_VERSION,
+};
 
 use ya_client_model::NodeId;
 use ya_negotiator_shared_lib_interface::plugin::{
@@ -21,221 +37,5166 @@ use ya_negotiator_shared_lib_interface::*;
 pub struct BlacklistReputation {
     config: Arc<BlacklistReputationsConfig>,
     state: Arc<Mutex<BlacklistState>>,
-    runtime: Runtime,
-    workdir: PathBuf,
+    /// Runtime executing timers and background tasks. Host runtime is reused if available.
+    handle: Handle,
+    /// Runtime owned by plugin, when there was no runtime to reuse.
+    _runtime: Option<Runtime>,
+    /// Background tasks aborted on drop.
+    tasks: Vec<JoinHandle<()>>,
+    /// Held as long as negotiator writes to state files.
+    _lock: Option<StateLock>,
+    /// Time source for payment deadlines.
+    clock: Arc<dyn Clock>,
+    /// Set by `shutdown`, after which state on disk is consistent and `Drop` does nothing.
+    shut_down: bool,
+    store: Arc<dyn ReputationStore>,
+    writer: BlacklistWriter,
+    /// Reloads blacklist file edited by operator.
+    _watcher: Option<RecommendedWatcher>,
+    /// Watches `kill_switch_path`.
+    _kill_switch: Option<RecommendedWatcher>,
+    metrics: Metrics,
 }
 
+#[derive(Default)]
 pub struct BlacklistState {
-    blacklist: Vec<NodeId>,
-    agreements: HashMap<String, TrackedAgreement>,
+    pub(crate) blacklist: HashMap<NodeId, BlacklistEntry>,
+    pub(crate) agreements: HashMap<String, TrackedAgreement>,
+    /// Factor in range (0.0, 1.0] by which `Score` of Node's proposals is multiplied.
+    /// Nodes not present in the map have clean payment history (factor 1.0).
+    pub(crate) reputation: HashMap<NodeId, f64>,
+    /// Number of unpaid Agreements per Node.
+    pub(crate) strikes: HashMap<NodeId, u32>,
+    /// Number of consecutive Agreements paid on time per Node. Reset by each strike.
+    pub(crate) paid_streak: HashMap<NodeId, u32>,
+    /// Proposals sent to Nodes, for which no Agreement was approved yet.
+    pub(crate) proposals: HashMap<String, (NodeId, DateTime<Utc>)>,
+    /// Negotiations abandoned by Node since it's last approved Agreement.
+    pub(crate) abandon_streak: HashMap<NodeId, u32>,
+    /// Agreements, which weren't paid in time, but can still be paid in `payment_grace`.
+    pub(crate) late: HashMap<String, LateAgreement>,
+    /// Nodes banned by operator in `static_blacklist` section of blacklist file.
+    /// They are never modified by plugin.
+    pub(crate) static_blacklist: HashMap<NodeId, BlacklistEntry>,
+    /// Payment history per Node.
+    pub(crate) stats: HashMap<NodeId, NodeStats>,
+    /// Nodes with recent default, which are still negotiable, but with lowered `Score`.
+    /// Value is time of the last default.
+    pub(crate) graylist: HashMap<NodeId, DateTime<Utc>>,
+    /// Accumulated amount of unpaid Agreements per Node.
+    pub(crate) unpaid: HashMap<NodeId, f64>,
+    /// Operator notes about Nodes, e.g. reference to fraud report. Kept regardless
+    /// if Node is blacklisted or not.
+    pub(crate) notes: HashMap<NodeId, String>,
+    /// Payment timeout learned from payment latencies with `auto_timeout`.
+    pub(crate) auto_timeout: Option<std::time::Duration>,
+    /// Blacklisted Nodes aren't rejected. Set by `enforcement_enabled` and kill switch file.
+    pub(crate) enforcement_disabled: bool,
+    /// Trusted Nodes loaded from whitelist file. Whitelist beats blacklist:
+    /// whitelisted Nodes are never blacklisted and their proposals are always accepted.
+    pub(crate) whitelist: HashSet<NodeId>,
+    /// Nodes blacklisted only by remote blacklist source. They are fetched on each
+    /// startup, so they aren't persisted locally.
+    pub(crate) remote: HashSet<NodeId>,
+    /// Time of last successful fetch of remote blacklist.
+    pub(crate) last_remote_sync: Option<DateTime<Utc>>,
+    /// Outcomes of Agreements recorded with `record_outcomes`, which weren't
+    /// appended to history yet.
+    pub(crate) history: Vec<AgreementOutcome>,
+    /// Receives event each time Node is blacklisted. Kept on state level,
+    /// so timer tasks can emit events as well.
+    pub(crate) events: Option<mpsc::Sender<BlacklistEvent>>,
+    pub(crate) metrics: Metrics,
+    /// Database updated on each change of blacklist and tracked Agreements.
+    /// If set, these are not written to state files.
+    pub(crate) backend: Option<Box<dyn StateBackend>>,
+}
+
+/// Notification about blacklisting Node for external monitoring.
+#[derive(Clone, Debug, Serialize)]
+pub struct BlacklistEvent {
+    pub node: NodeId,
+    pub reason: BlacklistReason,
+    /// Agreement, which caused blacklisting. Empty for manual actions.
+    pub agreement_id: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Payments needed before `auto_timeout` replaces configured payment timeout.
+const AUTO_TIMEOUT_MIN_SAMPLES: u32 = 20;
+/// Interval of recomputing `auto_timeout`.
+const AUTO_TIMEOUT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+/// Factor applied to `BlacklistReputation::reputation_score` of graylisted Nodes.
+pub const GRAYLIST_FACTOR: f64 = 0.5;
+/// Proposals, which weren't rejected or turned into Agreement in this time, are forgotten.
+const PROPOSAL_TRACKING_SECS: i64 = 3600;
+/// Payment timeouts below this value are likely too short for real payment settlement.
+const MIN_SAFE_PAYMENT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+/// Tokio timers use monotonic clock, which doesn't advance while system is suspended.
+/// Payment timers wake up at least this often to compare deadline with wall clock, so
+/// deadline passed during suspension is noticed shortly after wake up.
+const DEADLINE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Agreement not paid in `payment_timeout`. Strike is undone, if it is paid before `deadline`.
+#[derive(Clone)]
+pub(crate) struct LateAgreement {
+    node: NodeId,
+    signed: DateTime<Utc>,
+    policy: Option<String>,
+    amount: Option<f64>,
+    deadline: DateTime<Utc>,
+    terminated: Option<DateTime<Utc>>,
+    /// Strike counted for this Agreement.
+    strike: Strike,
+}
+
+/// Strike counted by `BlacklistState::count_strike`.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Strike {
+    /// Weight of counted strike. `None`, if strike wasn't counted, because Node is
+    /// whitelisted or its first offense was forgiven.
+    pub weight: Option<u32>,
+    /// Node was blacklisted because of this strike.
+    pub blacklisted: bool,
+}
+
+/// Payment history of Node.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct NodeStats {
+    /// Number of approved Agreements.
+    pub total: u32,
+    pub paid: u32,
+    /// Agreements not paid in time, with rejected Invoice or paid partially.
+    pub defaulted: u32,
+    /// Time of first Agreement (or abandoned negotiation) with Node. Proposals of Nodes,
+    /// which never signed Agreement, aren't recorded, so statistics don't grow with
+    /// each requestor on the market.
+    #[serde(default)]
+    pub first_seen: Option<DateTime<Utc>>,
+    /// Time of last proposal or Agreement event from Node.
+    #[serde(default)]
+    pub last_seen: Option<DateTime<Utc>>,
+    /// Time of last Agreement, which Node didn't pay.
+    #[serde(default)]
+    pub last_default: Option<DateTime<Utc>>,
+    /// Negotiations abandoned by Node without signing Agreement.
+    #[serde(default)]
+    pub abandoned: u32,
+    /// Time, when Node was last removed from blacklist thanks to `redemption_payments`.
+    #[serde(default)]
+    pub last_redeemed: Option<DateTime<Utc>>,
+    /// Time between Agreement termination and payment.
+    #[serde(default)]
+    pub payment_latency: PaymentLatency,
+}
+
+/// Upper bounds (in seconds) of `PaymentLatency` histogram buckets. Last bucket
+/// counts payments slower than the last bound.
+pub const LATENCY_BUCKETS: [u64; 5] = [10, 60, 300, 900, 3600];
+
+/// Distribution of payment latencies of Node.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PaymentLatency {
+    pub samples: u32,
+    pub mean_secs: f64,
+    pub max_secs: f64,
+    /// Number of payments in each of `LATENCY_BUCKETS` and above the last bound.
+    pub buckets: [u32; LATENCY_BUCKETS.len() + 1],
+}
+
+impl PaymentLatency {
+    /// Adds samples of another distribution.
+    pub fn merge(&mut self, other: &PaymentLatency) {
+        let samples = self.samples + other.samples;
+        if samples == 0 {
+            return;
+        }
+        self.mean_secs = (self.mean_secs * self.samples as f64
+            + other.mean_secs * other.samples as f64)
+            / samples as f64;
+        self.samples = samples;
+        self.max_secs = self.max_secs.max(other.max_secs);
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += count;
+        }
+    }
+
+    /// Upper estimate of `percentile` (0-100) of latencies. Bucket bounds are used,
+    /// so result is never lower than the real percentile.
+    pub fn percentile_secs(&self, percentile: f64) -> Option<f64> {
+        if self.samples == 0 {
+            return None;
+        }
+        let needed = (self.samples as f64 * percentile / 100.0).ceil().max(1.0) as u32;
+        let mut count = 0;
+        for (idx, bucket) in self.buckets.iter().enumerate() {
+            count += bucket;
+            if count >= needed {
+                return Some(match LATENCY_BUCKETS.get(idx) {
+                    Some(bound) => (*bound as f64).min(self.max_secs),
+                    None => self.max_secs,
+                });
+            }
+        }
+        Some(self.max_secs)
+    }
+
+    pub fn record(&mut self, latency: std::time::Duration) {
+        let secs = latency.as_secs_f64();
+        self.samples += 1;
+        self.mean_secs += (secs - self.mean_secs) / self.samples as f64;
+        self.max_secs = self.max_secs.max(secs);
+
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound as f64)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket] += 1;
+    }
+}
+
+impl NodeStats {
+    /// Fraction of Agreements, which Node didn't pay.
+    pub fn default_rate(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.defaulted as f64 / self.total as f64
+    }
+
+    /// Node had any Agreement or abandoned negotiation.
+    pub fn has_history(&self) -> bool {
+        self.total > 0 || self.paid > 0 || self.defaulted > 0 || self.abandoned > 0
+    }
+}
+
+/// Summary of plugin state returned by `BlacklistReputation::stats`.
+#[derive(Clone, Debug, Serialize)]
+pub struct ReputationStats {
+    pub blacklisted: usize,
+    pub tracked_agreements: usize,
+    /// Configured `max_tracked_agreements`.
+    pub max_tracked_agreements: Option<usize>,
+    /// Time of last successful synchronization with `blacklist_source`.
+    pub last_remote_sync: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum BlacklistReason {
+    /// Invoice wasn't paid in `payment_timeout`.
+    PaymentTimeout,
+    /// Requestor rejected Invoice.
+    InvoiceRejected,
+    /// Requestor paid less than amount due.
+    PartialPayment,
+    /// Node blacklisted by operator with explanation.
+    Manual(String),
+    /// Requestor repeatedly abandoned negotiations without signing Agreement.
+    AbandonedNegotiations,
+    /// Agreement wasn't terminated in `max_agreement_age`.
+    AgreementExpired,
+    /// Entry loaded from older blacklist format, which didn't store reasons.
+    Unknown,
+}
+
+impl std::fmt::Display for BlacklistReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlacklistReason::PaymentTimeout => write!(f, "Invoice not paid in time"),
+            BlacklistReason::InvoiceRejected => write!(f, "Invoice rejected"),
+            BlacklistReason::PartialPayment => write!(f, "Invoice paid partially"),
+            BlacklistReason::Manual(reason) => write!(f, "Banned by operator ({})", reason),
+            BlacklistReason::AbandonedNegotiations => write!(f, "Abandoning negotiations"),
+            BlacklistReason::AgreementExpired => write!(f, "Agreement not terminated"),
+            BlacklistReason::Unknown => write!(f, "Not paying Invoices"),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BlacklistEntry {
+    pub node: NodeId,
+    pub reason: BlacklistReason,
+    /// Time of adding Node to blacklist.
+    pub added: DateTime<Utc>,
+    /// Agreement, which caused blacklisting. `None` for manual bans.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agreement_id: Option<String>,
+    /// Policy of Agreement, which caused blacklisting. Determines TTL of the entry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<String>,
+    /// Network endpoint of requestor read from `endpoint_property`. Helps to correlate
+    /// bans with firewall rules.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+}
+
+impl BlacklistEntry {
+    pub fn new(node: NodeId, reason: BlacklistReason) -> BlacklistEntry {
+        BlacklistEntry {
+            node,
+            reason,
+            added: Utc::now(),
+            agreement_id: None,
+            policy: None,
+            endpoint: None,
+        }
+    }
+
+    pub fn with_agreement(mut self, agreement_id: &str) -> BlacklistEntry {
+        self.agreement_id = Some(agreement_id.to_string());
+        self
+    }
+
+    pub fn with_policy(mut self, policy: Option<String>) -> BlacklistEntry {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_endpoint(mut self, endpoint: Option<String>) -> BlacklistEntry {
+        self.endpoint = endpoint;
+        self
+    }
+
+    /// Ban made by operator. Such bans are lifted only by operator.
+    pub fn is_manual(&self) -> bool {
+        matches!(self.reason, BlacklistReason::Manual(_))
+    }
+
+    /// Time of removing entry from blacklist. Entries without TTL and bans
+    /// made by operator never expire.
+    pub fn expires(&self, ttl: Option<std::time::Duration>) -> Option<DateTime<Utc>> {
+        if self.is_manual() {
+            return None;
+        }
+        let ttl = chrono::Duration::from_std(ttl?).ok()?;
+        Some(self.added + ttl)
+    }
+
+    pub fn is_expired_at(&self, ttl: Option<std::time::Duration>, now: DateTime<Utc>) -> bool {
+        match self.expires(ttl) {
+            Some(expires) => now > expires,
+            None => false,
+        }
+    }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct TrackedAgreement {
     pub id: String,
     pub node: NodeId,
+    /// Approval time reported by requestor. Dates further than `max_clock_skew` in the future
+    /// are replaced by local time. Payment deadlines are computed from local `terminated` time.
     pub signed: DateTime<Utc>,
-    pub terminated: Option<Instant>,
+    /// Local time of termination.
+    pub terminated: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub pricing: Option<LinearPricing>,
+    /// Name of `policies` entry applied to Agreement. Global config is used if not set.
+    #[serde(default)]
+    pub policy: Option<String>,
+    /// Payment timeout agreed in offer (`golem.reputation.payment_timeout`). Takes
+    /// precedence over configured timeouts.
+    #[serde(default, with = "humantime_serde")]
+    pub payment_timeout: Option<std::time::Duration>,
+    /// Requestor endpoint from `endpoint_property` of the Demand.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Amount reported by `report_payment`, kept for auditing partial payments.
+    #[serde(default)]
+    pub amount_paid: Option<f64>,
+    /// Amount due estimated at the time of payment.
+    #[serde(default)]
+    pub amount_due: Option<f64>,
+    /// Payment deadline task. Aborted, when we get Invoice related event.
+    #[serde(skip)]
+    timer: Option<JoinHandle<()>>,
+}
+
+/// Cloned record doesn't own payment timer.
+impl Clone for TrackedAgreement {
+    fn clone(&self) -> Self {
+        TrackedAgreement {
+            id: self.id.clone(),
+            node: self.node,
+            signed: self.signed,
+            terminated: self.terminated,
+            pricing: self.pricing.clone(),
+            policy: self.policy.clone(),
+            payment_timeout: self.payment_timeout,
+            endpoint: self.endpoint.clone(),
+            amount_paid: self.amount_paid,
+            amount_due: self.amount_due,
+            timer: None,
+        }
+    }
+}
+
+/// Linear pricing model from Agreement. Coefficients correspond to `usage_vector` entries,
+/// with the last coefficient being constant price.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LinearPricing {
+    pub usage_vector: Vec<String>,
+    pub coeffs: Vec<f64>,
+}
+
+impl LinearPricing {
+    fn from_agreement(agreement: &AgreementView) -> Option<LinearPricing> {
+        Some(LinearPricing {
+            usage_vector: agreement
+                .pointer_typed("/offer/properties/golem/com/usage/vector")
+                .ok()?,
+            coeffs: agreement
+                .pointer_typed("/offer/properties/golem/com/pricing/model/linear/coeffs")
+                .ok()?,
+        })
+    }
+}
+
+impl TrackedAgreement {
+    pub fn new(id: String, node: NodeId, signed: DateTime<Utc>) -> TrackedAgreement {
+        TrackedAgreement {
+            id,
+            node,
+            signed,
+            terminated: None,
+            pricing: None,
+            policy: None,
+            payment_timeout: None,
+            endpoint: None,
+            amount_paid: None,
+            amount_due: None,
+            timer: None,
+        }
+    }
+
+    /// Estimates amount due for Agreement. Only constant price and duration are known
+    /// without Invoice, so this is lower bound of the real amount.
+    pub fn amount_due(&self) -> Option<f64> {
+        let pricing = self.pricing.as_ref()?;
+        let mut amount = *pricing.coeffs.last()?;
+
+        let duration_idx = pricing
+            .usage_vector
+            .iter()
+            .position(|usage| usage == "golem.usage.duration_sec");
+        if let (Some(idx), Some(terminated)) = (duration_idx, self.terminated) {
+            let duration = (terminated - self.signed).num_milliseconds().max(0) as f64 / 1000.0;
+            amount += pricing.coeffs.get(idx)? * duration;
+        }
+        Some(amount)
+    }
+
+    fn timer_active(&self) -> bool {
+        self.timer
+            .as_ref()
+            .map(|timer| !timer.is_finished())
+            .unwrap_or(false)
+    }
+
+    fn stop_timer(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            timer.abort();
+        }
+    }
+}
+
+/// Enforcement parameters for class of requestors. Parameters, which aren't set,
+/// are taken from global config.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PolicyConfig {
+    #[serde(default, with = "humantime_serde")]
+    pub payment_timeout: Option<std::time::Duration>,
+    #[serde(default)]
+    pub strikes_before_blacklist: Option<u32>,
+    #[serde(default, with = "humantime_serde")]
+    pub blacklist_ttl: Option<std::time::Duration>,
+}
+
+/// How `BlacklistReputation::merge_blacklist` combines imported entries with current blacklist.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MergeMode {
+    /// Only Nodes not blacklisted yet are added. Existing entries are kept unchanged.
+    Union,
+    /// Local blacklist is replaced by imported entries. Entries of remote blacklist
+    /// are managed by remote source, so they are kept.
+    Replace,
+}
+
+/// Handling of approved Agreements without `/approved_date`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingApprovedDate {
+    /// Use time of approval notification as signing time.
+    #[default]
+    Now,
+    /// Don't track such Agreements.
+    Skip,
+}
+
+impl FromStr for MissingApprovedDate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "now" => Ok(MissingApprovedDate::Now),
+            "skip" => Ok(MissingApprovedDate::Skip),
+            _ => Err(anyhow::anyhow!(
+                "Unknown missing approved date policy: {}",
+                s
+            )),
+        }
+    }
+}
+
+/// What to do with new Agreement, when `max_tracked_agreements` is reached.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackingLimitAction {
+    /// Don't track new Agreement.
+    #[default]
+    Refuse,
+    /// Stop tracking the oldest Agreement to make room for the new one.
+    Evict,
+}
+
+impl FromStr for TrackingLimitAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "refuse" => Ok(TrackingLimitAction::Refuse),
+            "evict" => Ok(TrackingLimitAction::Evict),
+            _ => Err(anyhow::anyhow!("Unknown tracking limit action: {}", s)),
+        }
+    }
+}
+
+/// Handling of the first default of Node without payment history.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FirstOffenseAction {
+    /// Apply the same rules as to other Nodes.
+    #[default]
+    Normal,
+    /// Record default in statistics, but don't give strike.
+    Forgive,
+    /// Blacklist Node immediately, regardless of strikes and unpaid amount.
+    Blacklist,
+}
+
+impl FromStr for FirstOffenseAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "normal" => Ok(FirstOffenseAction::Normal),
+            "forgive" => Ok(FirstOffenseAction::Forgive),
+            "blacklist" => Ok(FirstOffenseAction::Blacklist),
+            _ => Err(anyhow::anyhow!("Unknown first offense action: {}", s)),
+        }
+    }
 }
 
+/// Handling of proposals from blacklisted Nodes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlacklistAction {
+    /// Reject proposal.
+    #[default]
+    Reject,
+    /// Accept proposal with `Score` lowered by `blacklist_score_penalty`, so Node
+    /// is used only as a last resort.
+    ScorePenalty,
+}
+
+impl FromStr for BlacklistAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(BlacklistAction::Reject),
+            "score_penalty" => Ok(BlacklistAction::ScorePenalty),
+            _ => Err(anyhow::anyhow!("Unknown blacklist action: {}", s)),
+        }
+    }
+}
+
+/// Plugin configuration from negotiator config in YAML. Each field can be overridden by
+/// environment variable named after negotiator instance and field, uppercased:
+/// `REPUTATION_<NAME>_<FIELD>`, for example `REPUTATION_STRICT_PAYMENT_TIMEOUT=1m`
+/// for instance `strict`. Unnamed instance uses `REPUTATION_<FIELD>`. Values are parsed
+/// as YAML, so lists can be given as `[a, b]`.
 #[derive(StructOpt, Serialize, Deserialize)]
 pub struct BlacklistReputationsConfig {
+    #[serde(default = "default_payment_timeout")]
     #[serde(with = "humantime_serde")]
     #[structopt(long, env, parse(try_from_str = humantime::parse_duration), default_value = "15s")]
     pub payment_timeout: std::time::Duration,
+    /// Payment timeouts for Nodes, which need more (or less) time to settle than
+    /// global `payment_timeout`.
+    #[serde(default, with = "duration_map")]
+    #[structopt(skip)]
+    pub payment_timeout_overrides: HashMap<NodeId, std::time::Duration>,
+    /// Give strikes to Nodes, which didn't pay in `payment_timeout`. When disabled,
+    /// Nodes are blacklisted only for rejecting invoices.
+    #[serde(default = "default_enable_timeout_blacklist")]
+    #[structopt(long, env, parse(try_from_str), default_value = "true")]
+    pub enable_timeout_blacklist: bool,
+    /// Named policies overriding global enforcement parameters for chosen requestors.
+    #[serde(default)]
+    #[structopt(skip)]
+    pub policies: HashMap<String, PolicyConfig>,
+    /// Demand property (e.g. `golem.srv.caps.payload-manifest`), which value selects
+    /// policy for Agreement using `policy_mapping`.
+    #[serde(default)]
+    #[structopt(long, env)]
+    pub policy_property: Option<String>,
+    /// Maps values of `policy_property` to names of `policies`.
+    #[serde(default)]
+    #[structopt(skip)]
+    pub policy_mapping: HashMap<String, String>,
+    /// Demand property with network address of requestor (e.g. `golem.node.net.address`),
+    /// stored with blacklist entries. Not recorded if not set or missing in Demand.
+    #[serde(default)]
+    #[structopt(long, env)]
+    pub endpoint_property: Option<String>,
+    /// Agreements, which are never tracked for payment, e.g. test Agreements.
+    #[serde(default)]
+    #[structopt(long)]
+    pub ignore_agreement_ids: Vec<String>,
+    /// Maps demand properties (e.g. `golem.srv.comment`) to values marking Agreements, which
+    /// are never tracked for payment. Agreement is ignored, if any of properties matches.
+    #[serde(default)]
+    #[structopt(skip)]
+    pub ignore_agreements_matching: HashMap<String, String>,
+    /// Number of unpaid Agreements, after which Node is blacklisted.
+    #[serde(default = "default_strikes_before_blacklist")]
+    #[structopt(long, env, default_value = "1")]
+    pub strikes_before_blacklist: u32,
+    /// Number of strikes given for Agreement not paid in `payment_timeout`.
+    #[serde(default = "default_strike_weight")]
+    #[structopt(long, env, default_value = "1")]
+    pub timeout_strike_weight: u32,
+    /// Number of strikes given for rejected Invoice, which is stronger signal of bad faith
+    /// than slow payment.
+    #[serde(default = "default_strike_weight")]
+    #[structopt(long, env, default_value = "1")]
+    pub rejection_strike_weight: u32,
+    /// What to do, when Node, which neither paid nor defaulted before, defaults:
+    /// `normal`, `forgive` (new identities may make genuine mistakes) or `blacklist`
+    /// (new identities may be throwaway accounts).
+    #[serde(default)]
+    #[structopt(long, env, default_value = "normal")]
+    pub first_offense_action: FirstOffenseAction,
+    /// If greater than 0, Node is blacklisted, when fraction of it's Agreements, which weren't
+    /// paid, exceeds this value. Replaces `strikes_before_blacklist`.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "0.0")]
+    pub default_rate_threshold: f64,
+    /// Minimal number of Agreements with Node, before `default_rate_threshold` is applied.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "0")]
+    pub min_samples: u32,
+    /// Node is blacklisted only if accumulated amount of unpaid Agreements exceeds this value.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "0.0")]
+    pub min_unpaid_amount: f64,
+    /// Percentage of amount due, which can be missing from payment reported with
+    /// `report_payment`, before it is treated as partial payment.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "0.0")]
+    pub allow_partial_payment_pct: f64,
+    /// Time after which Node is removed from blacklist. Blacklisting is permanent if not set.
+    /// Nodes banned manually by operator are never removed.
+    #[serde(default, with = "humantime_serde")]
+    #[structopt(long, env, parse(try_from_str = humantime::parse_duration))]
+    pub blacklist_ttl: Option<std::time::Duration>,
+    /// Maximal number of blacklisted Nodes. The oldest entries are evicted when exceeded.
+    #[serde(default)]
+    #[structopt(long, env)]
+    pub max_blacklist_size: Option<usize>,
+    /// Add `golem.reputation.*` properties with payment policy to offers, so requestors
+    /// know what to expect.
+    #[serde(default)]
+    #[structopt(long)]
+    pub advertise_policy: bool,
+    /// Only log decisions without rejecting proposals or modifying blacklist.
+    #[serde(default)]
+    #[structopt(long)]
+    pub dry_run: bool,
+    /// Payment platforms accepted from requestors, e.g. `erc20-polygon-glm`.
+    /// Proposals allowing only other platforms are rejected, unless requestor is whitelisted.
+    /// All platforms are accepted if empty.
+    #[serde(default)]
+    #[structopt(long)]
+    pub allowed_platforms: Vec<String>,
+    /// Message sent to rejected requestors. Supports placeholders: `{node}`, `{reason}`,
+    /// `{added}` and `{expires}`.
+    #[serde(default)]
+    #[structopt(long, env)]
+    pub reject_message_template: Option<String>,
+    /// Destination of plugin logs: `file` in working directory, `syslog` or `both`.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "file")]
+    pub log_target: LogTarget,
+    /// Format of plugin log file: `text` or `json`.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "text")]
+    pub log_format: LogFormat,
+    /// Size in bytes, after which log file is rotated. Log file grows unbounded, if not set.
+    #[serde(default)]
+    #[structopt(long, env)]
+    pub log_max_size: Option<u64>,
+    /// Number of rotated log files to keep. Requires `log_max_size`.
+    #[serde(default)]
+    #[structopt(long, env)]
+    pub log_keep_files: Option<usize>,
+    /// Format of state files: `yaml` or `json`. Determines file extensions.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "yaml")]
+    pub persistence_format: Format,
+    /// Location of blacklist file. Can be shared by multiple plugin instances.
+    /// Relative paths are resolved against working directory.
+    #[serde(default)]
+    #[structopt(long, env, parse(from_os_str))]
+    pub blacklist_path: Option<PathBuf>,
+    /// Reject also Nodes blacklisted by other plugin instances in the same working
+    /// directory (`blacklist-<name>` files). Files of other instances are rescanned every
+    /// 10 seconds. Works only with file backend. Custom stores can share blacklist
+    /// by implementing `ReputationStore::find_blacklisted`.
+    #[serde(default)]
+    #[structopt(long)]
+    pub share_blacklist: bool,
+    /// Reject blacklisted Nodes. When disabled, all proposals pass through, but Agreements
+    /// are still tracked and Nodes are still blacklisted.
+    #[serde(default = "default_enforcement_enabled")]
+    #[structopt(long, env, parse(try_from_str), default_value = "true")]
+    pub enforcement_enabled: bool,
+    /// Enforcement is disabled as long as this file exists. The file is watched, so
+    /// rejections can be stopped instantly in emergency without restart. Relative paths
+    /// are resolved against working directory.
+    #[serde(default)]
+    #[structopt(long, env, parse(from_os_str))]
+    pub kill_switch_path: Option<PathBuf>,
+    /// Location of tracked Agreements file. Relative paths are resolved against
+    /// working directory.
+    #[serde(default)]
+    #[structopt(long, env, parse(from_os_str))]
+    pub agreements_path: Option<PathBuf>,
+    /// URL of blacklist shared by multiple providers. It is fetched on startup and merged
+    /// with local blacklist. See `remote` module for expected response format.
+    #[serde(default)]
+    #[structopt(long, env)]
+    pub blacklist_source: Option<Url>,
+    /// Interval of re-fetching `blacklist_source`. If not set, remote blacklist
+    /// is fetched only on startup.
+    #[serde(default, with = "humantime_serde")]
+    #[structopt(long, env, parse(try_from_str = humantime::parse_duration))]
+    pub remote_refresh_interval: Option<std::time::Duration>,
+    /// Handling of invalid blacklist entries: `skip` logs and ignores them,
+    /// `fail` refuses to load whole blacklist.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "skip")]
+    pub on_parse_error: OnParseError,
+    /// Refuse to start, if blacklist file exists, but can't be read or parsed.
+    /// Otherwise plugin starts with empty blacklist and the file is backed up.
+    #[serde(default)]
+    #[structopt(long)]
+    pub fail_on_load_error: bool,
+    /// Refuse to start, if state files can't be written. Otherwise plugin only warns,
+    /// that state learned during the session will be lost on restart.
+    #[serde(default)]
+    #[structopt(long)]
+    pub fail_on_unwritable_workdir: bool,
+    /// Maximum number of tracked Agreements (and payment timers). Unlimited if not set.
+    #[serde(default)]
+    #[structopt(long, env)]
+    pub max_tracked_agreements: Option<usize>,
+    /// What to do, when `max_tracked_agreements` is reached: `refuse` to track new
+    /// Agreement or `evict` the oldest one.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "refuse")]
+    pub tracking_limit_action: TrackingLimitAction,
+    /// What to do with approved Agreements without `/approved_date`: `now` uses current
+    /// time as signing time, `skip` doesn't track them.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "now")]
+    pub missing_approved_date: MissingApprovedDate,
+    /// Tolerated difference between requestor's `/approved_date` and local clock.
+    #[serde(default = "default_max_clock_skew")]
+    #[serde(with = "humantime_serde")]
+    #[structopt(long, env, parse(try_from_str = humantime::parse_duration), default_value = "1m")]
+    pub max_clock_skew: std::time::Duration,
+    /// Storage of blacklist and tracked Agreements: `file` or `sqlite`.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "file")]
+    pub backend: Backend,
+    /// Value subtracted from `Score` of Nodes, which didn't have any Agreement with us yet.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "0.0")]
+    pub new_node_score_penalty: f64,
+    /// Value subtracted from `Score` of graylisted Nodes. Node is graylisted after each default,
+    /// which didn't result in blacklisting.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "0.0")]
+    pub graylist_penalty: f64,
+    /// Time after last default, after which Node is removed from graylist.
+    /// Graylisting lasts until blacklisting if not set.
+    #[serde(default, with = "humantime_serde")]
+    #[structopt(long, env, parse(try_from_str = humantime::parse_duration))]
+    pub graylist_ttl: Option<std::time::Duration>,
+    /// What to do with proposals from blacklisted Nodes: `reject` or `score_penalty`.
+    /// Nodes from static blacklist are always rejected.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "reject")]
+    pub blacklist_action: BlacklistAction,
+    /// Value subtracted from `Score` of blacklisted Nodes, when `blacklist_action`
+    /// is `score_penalty`.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "0.0")]
+    pub blacklist_score_penalty: f64,
+    /// Number of consecutive negotiations abandoned by Node without signing Agreement,
+    /// after which Node is graylisted. 0 disables counting abandoned negotiations.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "0")]
+    pub abandon_threshold: u32,
+    /// Blacklist Nodes reaching `abandon_threshold` instead of graylisting them.
+    #[serde(default)]
+    #[structopt(long)]
+    pub abandon_blacklist: bool,
+    /// Number of consecutive Agreements paid on time, after which blacklisted Node is removed
+    /// from blacklist. When enabled, blacklisted Nodes aren't rejected, but their proposals
+    /// are scored according to reputation, so they have a chance to redeem themselves.
+    /// Nodes banned manually by operator are always rejected. 0 disables redemption.
+    #[serde(default)]
+    #[structopt(long, env, default_value = "0")]
+    pub redemption_payments: u32,
+    /// Time after redemption, during which Node needs `post_redemption_strikes` strikes
+    /// more than usual to be blacklisted again.
+    #[serde(default, with = "humantime_serde")]
+    #[structopt(long, env, parse(try_from_str = humantime::parse_duration))]
+    pub post_redemption_cooldown: Option<std::time::Duration>,
+    #[serde(default = "default_post_redemption_strikes")]
+    #[structopt(long, env, default_value = "1")]
+    pub post_redemption_strikes: u32,
+    /// Invoices paid within this time after `payment_timeout` are accepted: strike is undone
+    /// and Node is removed from blacklist, if it was blacklisted because of this Agreement.
+    #[serde(default, with = "humantime_serde")]
+    #[structopt(long, env, parse(try_from_str = humantime::parse_duration))]
+    pub payment_grace: Option<std::time::Duration>,
+    /// Time given to Nodes to pay for Agreements, which payment deadline passed while plugin
+    /// wasn't running. Such Nodes get strike immediately after startup if not set.
+    #[serde(default, with = "humantime_serde")]
+    #[structopt(long, env, parse(try_from_str = humantime::parse_duration))]
+    pub restart_grace_period: Option<std::time::Duration>,
+    /// Reputation factor of Node is multiplied by this value on each non-payment.
+    #[serde(default = "default_reputation_penalty")]
+    #[structopt(long, env, default_value = "0.5")]
+    pub reputation_penalty: f64,
+    /// Reputation factor recovered by Node in each `reputation_recovery_interval`.
+    #[serde(default = "default_reputation_recovery")]
+    #[structopt(long, env, default_value = "0.1")]
+    pub reputation_recovery: f64,
+    #[serde(default = "default_reputation_recovery_interval")]
+    #[serde(with = "humantime_serde")]
+    #[structopt(long, env, parse(try_from_str = humantime::parse_duration), default_value = "1h")]
+    pub reputation_recovery_interval: std::time::Duration,
+    /// Penalty decay model. Replaces `reputation_penalty` and periodic recovery,
+    /// Node's score factor is computed from time of it's last default.
+    #[serde(default)]
+    #[structopt(skip)]
+    pub decay: Option<DecayConfig>,
+    /// Interval of writing state to disk. Blacklist is additionally written
+    /// each time a Node is blacklisted.
+    #[serde(default = "default_flush_interval")]
+    #[serde(with = "humantime_serde")]
+    #[structopt(long, env, parse(try_from_str = humantime::parse_duration), default_value = "1m")]
+    pub flush_interval: std::time::Duration,
+    /// Agreements not terminated within this time after signing are no longer tracked.
+    /// They are kept until termination, if not set.
+    #[serde(default, with = "humantime_serde")]
+    #[structopt(long, env, parse(try_from_str = humantime::parse_duration))]
+    pub max_agreement_age: Option<std::time::Duration>,
+    /// With `enable_timeout_blacklist` disabled, terminated Agreements wait only for
+    /// invoice rejection. They are no longer tracked this long after termination.
+    #[serde(default = "default_max_terminated_age")]
+    #[serde(with = "humantime_serde")]
+    #[structopt(long, env, parse(try_from_str = humantime::parse_duration), default_value = "7days")]
+    pub max_terminated_age: std::time::Duration,
+    /// Append outcome of each Agreement (payment, invoice rejection or payment timeout)
+    /// to history file, which can be replayed with `simulate` to tune configuration.
+    #[serde(default)]
+    #[structopt(long)]
+    pub record_outcomes: bool,
+    /// Replace global `payment_timeout` with `auto_timeout_percentile` of observed payment
+    /// latencies plus `auto_timeout_margin`, recomputed periodically. Global timeout is used
+    /// until enough payments are observed. Node overrides and policies are not affected.
+    /// Timeout agreed in offer wins over learned timeout, but with `advertise_policy`
+    /// the learned timeout is what gets advertised and agreed.
+    #[serde(default)]
+    #[structopt(long)]
+    pub auto_timeout: bool,
+    #[serde(default = "default_auto_timeout_percentile")]
+    #[structopt(long, env, default_value = "95")]
+    pub auto_timeout_percentile: f64,
+    #[serde(default = "default_auto_timeout_margin")]
+    #[serde(with = "humantime_serde")]
+    #[structopt(long, env, parse(try_from_str = humantime::parse_duration), default_value = "1m")]
+    pub auto_timeout_margin: std::time::Duration,
+    /// Upper limit of learned payment timeout.
+    #[serde(default, with = "humantime_serde")]
+    #[structopt(long, env, parse(try_from_str = humantime::parse_duration))]
+    pub auto_timeout_max: Option<std::time::Duration>,
+    /// Count Agreements not terminated in `max_agreement_age` as defaults, so requestors
+    /// can't avoid payment enforcement by letting Agreements expire.
+    #[serde(default)]
+    #[structopt(long)]
+    pub strike_expired_agreements: bool,
+    /// Interval of removing expired entries from blacklist and Agreements older than
+    /// `max_agreement_age` (or `max_terminated_age`). Defaults to tenth part of the
+    /// shortest of these periods.
+    #[serde(default, with = "humantime_serde")]
+    #[structopt(long, env, parse(try_from_str = humantime::parse_duration))]
+    pub expiry_sweep_interval: Option<std::time::Duration>,
+    /// Blacklist changes made within this time are written to disk together.
+    /// Each change is written immediately, if not set.
+    #[serde(default, with = "humantime_serde")]
+    #[structopt(long, env, parse(try_from_str = humantime::parse_duration))]
+    pub blacklist_write_debounce: Option<std::time::Duration>,
+    /// Log on shutdown Nodes, which would be removed from blacklist within
+    /// `shutdown_report_window`. Helps to tune `blacklist_ttl`.
+    #[serde(default)]
+    #[structopt(long)]
+    pub shutdown_report: bool,
+    #[serde(default = "default_shutdown_report_window")]
+    #[serde(with = "humantime_serde")]
+    #[structopt(long, env, parse(try_from_str = humantime::parse_duration), default_value = "1day")]
+    pub shutdown_report_window: std::time::Duration,
 }
 
-impl NegotiatorConstructor<BlacklistReputation> for BlacklistReputation {
-    fn new(
-        _name: &str,
-        config: serde_yaml::Value,
-        working_dir: PathBuf,
-    ) -> anyhow::Result<BlacklistReputation> {
-        let config: BlacklistReputationsConfig = serde_yaml::from_value(config)?;
-        let runtime = Runtime::new()?;
-
-        flexi_logger::Logger::try_with_env()?
-            .log_to_file(
-                flexi_logger::FileSpec::default()
-                    .directory(&working_dir)
-                    .basename("blacklist"),
-            )
-            .start()?;
+impl BlacklistReputationsConfig {
+    /// Renders `reject_message_template` for blacklisted Node. `expires` is `None`
+    /// for permanent bans.
+    pub fn reject_message(&self, entry: &BlacklistEntry, expires: Option<DateTime<Utc>>) -> String {
+        let template = match (&self.reject_message_template, expires) {
+            (Some(template), _) => template,
+            (None, Some(expires)) => {
+                return format!(
+                    "Node is blacklisted until {}: {}.",
+                    expires.to_rfc3339(),
+                    entry.reason
+                )
+            }
+            (None, None) => return format!("Node is blacklisted: {}.", entry.reason),
+        };
 
-        let blacklist = match fs::read_to_string(working_dir.join("blacklist.yaml")) {
-            Ok(content) => serde_yaml::from_str(&content)?,
-            Err(_) => vec![],
+        let expires = match expires {
+            Some(expires) => expires.to_rfc3339(),
+            None => "never".to_string(),
         };
 
-        log::info!("Starting BlacklistReputation plugin.");
+        template
+            .replace("{node}", &entry.node.to_string())
+            .replace("{reason}", &entry.reason.to_string())
+            .replace("{added}", &entry.added.to_rfc3339())
+            .replace("{expires}", &expires)
+    }
 
-        Ok(BlacklistReputation {
-            config: Arc::new(config),
-            state: Arc::new(Mutex::new(BlacklistState {
-                blacklist,
-                agreements: Default::default(),
-            })),
-            runtime,
-            workdir: working_dir,
-        })
+    /// Payment timeout for Node with overrides taken into account.
+    pub fn payment_timeout_for(&self, node: &NodeId) -> std::time::Duration {
+        self.payment_timeout_overrides
+            .get(node)
+            .cloned()
+            .unwrap_or(self.payment_timeout)
     }
-}
 
-impl Drop for BlacklistReputation {
-    fn drop(&mut self) {
-        let blacklist = {
-            self.state
-                .lock()
-                .unwrap()
-                .blacklist
-                .drain(..)
-                .collect::<Vec<NodeId>>()
-        };
+    /// Payment timeout for Agreement with `policy`. Node overrides take precedence over policy.
+    pub fn payment_timeout_with_policy(
+        &self,
+        node: &NodeId,
+        policy: Option<&str>,
+    ) -> std::time::Duration {
+        if let Some(timeout) = self.payment_timeout_overrides.get(node) {
+            return *timeout;
+        }
+        self.policy(policy)
+            .and_then(|policy| policy.payment_timeout)
+            .unwrap_or(self.payment_timeout)
+    }
 
-        if let Ok(content) = serde_yaml::to_string(&blacklist) {
-            fs::write(self.workdir.join("blacklist.yaml"), content).ok();
+    /// Payment timeout for Node and policy, with global `payment_timeout` replaced
+    /// by timeout learned with `auto_timeout`.
+    pub fn effective_payment_timeout(
+        &self,
+        node: &NodeId,
+        policy: Option<&str>,
+        auto: Option<std::time::Duration>,
+    ) -> std::time::Duration {
+        match auto {
+            Some(auto) if !self.has_configured_timeout(node, policy) => auto,
+            _ => self.payment_timeout_with_policy(node, policy),
         }
     }
-}
 
-impl NegotiatorComponent for BlacklistReputation {
-    /// BlacklistReputation will reject any Node on blacklist.
-    fn negotiate_step(
-        &mut self,
-        demand: &ProposalView,
-        offer: ProposalView,
-        score: Score,
-    ) -> anyhow::Result<NegotiationResult> {
-        if self
-            .state
-            .lock()
-            .unwrap()
-            .blacklist
-            .contains(&demand.issuer)
-        {
-            log::info!("Rejecting blacklisted node: {}", demand.issuer);
+    /// Payment timeout of tracked Agreement. Timeout agreed in offer takes precedence.
+    /// With `advertise_policy` enabled, the agreed timeout is the effective timeout
+    /// (including `auto_timeout`) at the time of negotiation, so learned timeout applies
+    /// to Agreements negotiated after it was computed.
+    pub fn payment_timeout_of(
+        &self,
+        record: &TrackedAgreement,
+        auto: Option<std::time::Duration>,
+    ) -> std::time::Duration {
+        if let Some(timeout) = record.payment_timeout {
+            return timeout;
+        }
+        self.effective_payment_timeout(&record.node, record.policy.as_deref(), auto)
+    }
 
-            return Ok(NegotiationResult::Reject {
-                reason: RejectReason::new("Node is blacklisted due to not paying Invoices."),
-                is_final: true,
-            });
+    /// Node has timeout from node override or policy.
+    fn has_configured_timeout(&self, node: &NodeId, policy: Option<&str>) -> bool {
+        self.payment_timeout_overrides.contains_key(node)
+            || self
+                .policy(policy)
+                .and_then(|policy| policy.payment_timeout)
+                .is_some()
+    }
+
+    /// Age after termination, at which Agreements without payment timer are no longer
+    /// tracked. `None`, if payment timers are enabled.
+    pub fn untimed_agreement_age(&self) -> Option<std::time::Duration> {
+        if self.enable_timeout_blacklist {
+            None
+        } else {
+            Some(self.max_terminated_age)
         }
+    }
 
-        log::debug!("Node {} allowed (not blacklisted).", demand.issuer);
-        Ok(NegotiationResult::Ready {
-            proposal: offer,
-            score,
-        })
+    /// Time, until which terminated Agreement should be paid.
+    pub fn payment_deadline(
+        &self,
+        record: &TrackedAgreement,
+        auto: Option<std::time::Duration>,
+    ) -> Option<DateTime<Utc>> {
+        let timeout = self.payment_timeout_of(record, auto);
+        let timeout = chrono::Duration::from_std(timeout).ok()?;
+        Some(record.terminated? + timeout)
     }
 
-    /// Negotiator will expect Invoice to be paid in specified deadline after termination.
-    /// We must store timestamp
-    fn on_agreement_terminated(
-        &mut self,
-        agreement_id: &str,
-        _result: &AgreementResult,
-    ) -> anyhow::Result<()> {
-        log::trace!("on_agreement_terminated [{}]", agreement_id);
+    pub fn policy(&self, name: Option<&str>) -> Option<&PolicyConfig> {
+        self.policies.get(name?)
+    }
 
-        let mut state = self.state.lock().unwrap();
-        if let Some(record) = state.agreements.get_mut(agreement_id) {
-            let now = Instant::now();
-            let state = self.state.clone();
-            let deadline = now + self.config.payment_timeout;
-            let agreement_id = agreement_id.to_string();
+    pub fn strikes_before_blacklist_for(&self, policy: Option<&str>) -> u32 {
+        self.policy(policy)
+            .and_then(|policy| policy.strikes_before_blacklist)
+            .unwrap_or(self.strikes_before_blacklist)
+    }
 
-            record.terminated = Some(now);
-            let node_id = record.node;
+    /// Number of strikes given for single default.
+    pub fn strike_weight(&self, reason: &BlacklistReason) -> u32 {
+        match reason {
+            BlacklistReason::PaymentTimeout => self.timeout_strike_weight,
+            BlacklistReason::InvoiceRejected => self.rejection_strike_weight,
+            _ => 1,
+        }
+    }
 
-            log::debug!(
-                "Setting timer ({}) for agreement [{}], node: {}",
-                humantime::format_duration(self.config.payment_timeout),
-                agreement_id,
-                node_id
-            );
+    /// Strikes needed to blacklist Node for Agreements with `policy`.
+    /// Recently redeemed Nodes are given `post_redemption_strikes` more.
+    pub fn strikes_required(
+        &self,
+        stats: &NodeStats,
+        policy: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> u32 {
+        let required = self.strikes_before_blacklist_for(policy);
+        let cooldown = self
+            .post_redemption_cooldown
+            .and_then(|cooldown| chrono::Duration::from_std(cooldown).ok());
+        match (stats.last_redeemed, cooldown) {
+            (Some(redeemed), Some(cooldown)) if now < redeemed + cooldown => {
+                required + self.post_redemption_strikes
+            }
+            _ => required,
+        }
+    }
 
-            self.runtime.spawn(async move {
-                tokio::time::sleep_until(deadline).await;
+    /// TTL of blacklist entry depending on policy of Agreement, which caused it.
+    pub fn blacklist_ttl_for(&self, entry: &BlacklistEntry) -> Option<std::time::Duration> {
+        self.policy(entry.policy.as_deref())
+            .and_then(|policy| policy.blacklist_ttl)
+            .or(self.blacklist_ttl)
+    }
 
-                let mut state = state.lock().unwrap();
+    /// Interval of expired entries sweep. `None` if blacklist entries never expire.
+    pub fn sweep_interval(&self) -> Option<std::time::Duration> {
+        if let Some(interval) = self.expiry_sweep_interval {
+            return Some(interval);
+        }
+        let shortest_ttl = self
+            .policies
+            .values()
+            .filter_map(|policy| policy.blacklist_ttl)
+            .chain(self.blacklist_ttl)
+            .chain(self.max_agreement_age)
+            .chain(self.untimed_agreement_age())
+            .min()?;
+        Some((shortest_ttl / 10).max(std::time::Duration::from_secs(1)))
+    }
 
-                // If we don't find Agreement in the map, it have been paid.
-                if let Some(record) = state.agreements.remove(&agreement_id) {
-                    log::info!(
-                        "Node [{}] didn't pay agreement [{}]. Blacklisting..",
-                        node_id,
-                        agreement_id
-                    );
-                    state.blacklist.push(record.node);
-                }
-            });
+    /// Agreement shouldn't be tracked according to `ignore_agreement_ids` and
+    /// `ignore_agreements_matching`.
+    pub fn is_ignored(&self, agreement_id: &str, properties: Option<&serde_json::Value>) -> bool {
+        if self
+            .ignore_agreement_ids
+            .iter()
+            .any(|id| id == agreement_id)
+        {
+            return true;
         }
-        Ok(())
+        let properties = match properties {
+            Some(properties) => properties,
+            None => return false,
+        };
+        self.ignore_agreements_matching
+            .iter()
+            .any(|(property, expected)| {
+                let pointer = format!("/{}", property.replace('.', "/"));
+                let value = properties.pointer(&pointer).map(|value| match value {
+                    serde_json::Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                });
+                value.as_ref() == Some(expected)
+            })
     }
 
-    /// Store `Agreement` information and track it's state.
-    fn on_agreement_approved(&mut self, agreement: &AgreementView) -> anyhow::Result<()> {
-        log::trace!("on_agreement_approved [{}]", agreement.id);
+    /// Value of `endpoint_property` in Demand properties.
+    pub fn endpoint(&self, properties: &serde_json::Value) -> Option<String> {
+        let property = self.endpoint_property.as_ref()?;
+        let pointer = format!("/{}", property.replace('.', "/"));
+        match properties.pointer(&pointer)? {
+            serde_json::Value::String(value) => Some(value.clone()),
+            serde_json::Value::Null => None,
+            value => Some(value.to_string()),
+        }
+    }
 
-        let record = TrackedAgreement {
-            id: agreement.id.clone(),
-            node: agreement.requestor_id()?,
-            signed: agreement
-                .pointer_typed::<DateTime<Utc>>("/approved_date")
-                .unwrap_or(Utc::now()),
-            terminated: None,
+    /// Chooses policy based on value of `policy_property` in Demand properties.
+    pub fn select_policy(&self, properties: &serde_json::Value) -> Option<String> {
+        let property = self.policy_property.as_ref()?;
+        let pointer = format!("/{}", property.replace('.', "/"));
+        let value = match properties.pointer(&pointer)? {
+            serde_json::Value::String(value) => value.clone(),
+            value => value.to_string(),
         };
 
-        {
-            self.state
-                .lock()
-                .unwrap()
-                .agreements
-                .insert(agreement.id.clone(), record);
-
-            log::info!(
-                "Registered agreement [{}] for node [{}].",
-                agreement.id,
-                agreement.requestor_id()?
+        let name = self.policy_mapping.get(&value)?;
+        if !self.policies.contains_key(name) {
+            log::warn!(
+                "Policy [{}] mapped from {}={} is not defined. Using global config.",
+                name,
+                property,
+                value
             );
-            Ok(())
+            return None;
         }
+        Some(name.clone())
     }
+}
 
-    /// Notifies `NegotiatorComponent`, about events related to Agreement appearing after
-    /// it's termination.
-    fn on_agreement_event(
-        &mut self,
-        agreement_id: &str,
-        event: &AgreementEvent,
-    ) -> anyhow::Result<()> {
-        log::trace!("on_agreement_event [{}]", agreement_id);
+/// Serializes map of durations in humantime format.
+mod duration_map {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use ya_client_model::NodeId;
 
-        let mut state = self.state.lock().unwrap();
-        match event {
-            AgreementEvent::InvoicePaid => {
-                if let Some(record) = state.agreements.remove(agreement_id) {
-                    log::info!(
-                        "Node [{}] paid invoice for agreement [{}]. Stop tracking..",
-                        record.node,
-                        agreement_id
-                    );
-                }
-                Ok(())
-            }
-            AgreementEvent::InvoiceRejected => {
-                if let Some(record) = state.agreements.remove(agreement_id) {
-                    log::info!(
-                        "Node [{}] rejected invoice for agreement [{}]. Blacklisting..",
-                        record.node,
-                        agreement_id
-                    );
-                    state.blacklist.push(record.node)
-                }
-                Ok(())
-            }
-            _ => Ok(()),
-        }
+    pub fn serialize<S: Serializer>(
+        map: &HashMap<NodeId, Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(
+            map.iter()
+                .map(|(node, timeout)| (node, humantime_serde::Serde::from(*timeout))),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<NodeId, Duration>, D::Error> {
+        let map = HashMap::<NodeId, humantime_serde::Serde<Duration>>::deserialize(deserializer)?;
+        Ok(map
+            .into_iter()
+            .map(|(node, timeout)| (node, timeout.into_inner()))
+            .collect())
     }
 }
 
-register_negotiators!(BlacklistReputation);
+/// Configuration with default values of all options. Together with `MemoryStore`
+/// it allows to construct negotiator without touching filesystem.
+impl Default for BlacklistReputationsConfig {
+    fn default() -> Self {
+        serde_yaml::from_value(serde_yaml::Value::Mapping(Default::default()))
+            .expect("All config fields have defaults")
+    }
+}
+
+fn default_payment_timeout() -> std::time::Duration {
+    std::time::Duration::from_secs(15)
+}
+
+fn default_auto_timeout_percentile() -> f64 {
+    95.0
+}
+
+fn default_auto_timeout_margin() -> std::time::Duration {
+    std::time::Duration::from_secs(60)
+}
+
+fn default_enforcement_enabled() -> bool {
+    true
+}
+
+fn default_strike_weight() -> u32 {
+    1
+}
+
+fn default_post_redemption_strikes() -> u32 {
+    1
+}
+
+fn default_enable_timeout_blacklist() -> bool {
+    true
+}
+
+fn default_strikes_before_blacklist() -> u32 {
+    1
+}
+
+fn default_reputation_penalty() -> f64 {
+    0.5
+}
+
+fn default_reputation_recovery() -> f64 {
+    0.1
+}
+
+fn default_reputation_recovery_interval() -> std::time::Duration {
+    std::time::Duration::from_secs(3600)
+}
+
+fn default_shutdown_report_window() -> std::time::Duration {
+    std::time::Duration::from_secs(24 * 3600)
+}
+
+fn default_max_clock_skew() -> std::time::Duration {
+    std::time::Duration::from_secs(60)
+}
+
+fn default_flush_interval() -> std::time::Duration {
+    std::time::Duration::from_secs(60)
+}
+
+fn default_max_terminated_age() -> std::time::Duration {
+    std::time::Duration::from_secs(7 * 24 * 3600)
+}
+
+/// Content of blacklist file.
+pub(crate) struct BlacklistFile {
+    entries: Vec<BlacklistEntry>,
+    /// `static_blacklist` section managed by operator. Items are kept as read,
+    /// so the section can be written back unchanged.
+    static_items: Option<Vec<serde_json::Value>>,
+    static_entries: Vec<BlacklistEntry>,
+}
+
+/// Blacklist file with both sections. File without `static_blacklist` section
+/// can be plain list of entries.
+#[derive(Deserialize)]
+struct BlacklistSections {
+    #[serde(default)]
+    blacklist: Vec<serde_json::Value>,
+    #[serde(default)]
+    static_blacklist: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BlacklistContent {
+    List(Vec<serde_json::Value>),
+    Sections(BlacklistSections),
+}
+
+/// Item of blacklist file. Older versions stored only list of Nodes.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BlacklistItem {
+    Entry(BlacklistEntry),
+    Legacy(NodeId),
+}
+
+impl BlacklistFile {
+    pub(crate) fn new(entries: Vec<BlacklistEntry>) -> BlacklistFile {
+        BlacklistFile {
+            entries,
+            static_items: None,
+            static_entries: vec![],
+        }
+    }
+
+    /// Parses blacklist item by item, so single malformed entry doesn't have to
+    /// invalidate whole file. Invalid entries are handled according to `on_error`.
+    pub(crate) fn parse(
+        format: Format,
+        content: &str,
+        on_error: OnParseError,
+    ) -> anyhow::Result<BlacklistFile> {
+        if content.trim().is_empty() {
+            return Ok(BlacklistFile::new(vec![]));
+        }
+
+        Ok(match format.deserialize::<BlacklistContent>(content)? {
+            BlacklistContent::List(items) => {
+                BlacklistFile::new(parse_items("blacklist", items, on_error)?)
+            }
+            BlacklistContent::Sections(sections) => BlacklistFile {
+                entries: parse_items("blacklist", sections.blacklist, on_error)?,
+                static_entries: parse_items(
+                    "static_blacklist",
+                    sections.static_blacklist.clone(),
+                    on_error,
+                )?,
+                static_items: Some(sections.static_blacklist),
+            },
+        })
+    }
+
+    /// Nodes from dynamic part of blacklist.
+    pub(crate) fn nodes(&self) -> HashSet<NodeId> {
+        self.entries.iter().map(|entry| entry.node).collect()
+    }
+
+    /// Raw `static_blacklist` section, if file has one.
+    pub(crate) fn static_items(&self) -> Option<&Vec<serde_json::Value>> {
+        self.static_items.as_ref()
+    }
+
+    /// Nodes banned permanently in `static_blacklist` section.
+    pub(crate) fn static_blacklist(&self) -> HashMap<NodeId, BlacklistEntry> {
+        self.static_entries
+            .iter()
+            .map(|entry| (entry.node, entry.clone()))
+            .collect()
+    }
+
+    /// Older versions could store the same Node multiple times. Duplicates are merged
+    /// keeping the oldest entry. Returns number of removed duplicates.
+    pub(crate) fn into_blacklist(self) -> (HashMap<NodeId, BlacklistEntry>, usize) {
+        let entries = self.entries;
+
+        let total = entries.len();
+        let mut blacklist = HashMap::new();
+        for entry in entries {
+            match blacklist.entry(entry.node) {
+                Entry::Vacant(vacant) => {
+                    vacant.insert(entry);
+                }
+                Entry::Occupied(mut occupied) => {
+                    if entry.added < occupied.get().added {
+                        occupied.insert(entry);
+                    }
+                }
+            }
+        }
+
+        let duplicates = total - blacklist.len();
+        (blacklist, duplicates)
+    }
+}
+
+/// Parses NodeId written by hand. Accepts hex in any case, with or without `0x` prefix.
+pub fn parse_node_id(s: &str) -> anyhow::Result<NodeId> {
+    let s = s.trim();
+    let hex = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    Ok(NodeId::from_str(&format!("0x{}", hex.to_lowercase()))?)
+}
+
+/// Replaces NodeIds in blacklist item with canonical form, so they can be deserialized.
+fn normalize_item(item: &mut serde_json::Value) {
+    let node = match item {
+        serde_json::Value::String(_) => item,
+        serde_json::Value::Object(entry) => match entry.get_mut("node") {
+            Some(node) => node,
+            None => return,
+        },
+        _ => return,
+    };
+    if let Some(parsed) = node.as_str().and_then(|s| parse_node_id(s).ok()) {
+        *node = serde_json::Value::String(parsed.to_string());
+    }
+}
+
+fn parse_items(
+    section: &str,
+    items: Vec<serde_json::Value>,
+    on_error: OnParseError,
+) -> anyhow::Result<Vec<BlacklistEntry>> {
+    let mut entries = Vec::with_capacity(items.len());
+    for (idx, mut item) in items.into_iter().enumerate() {
+        normalize_item(&mut item);
+        match serde_json::from_value::<BlacklistItem>(item.clone()) {
+            Ok(BlacklistItem::Entry(entry)) => entries.push(entry),
+            Ok(BlacklistItem::Legacy(node)) => {
+                entries.push(BlacklistEntry::new(node, BlacklistReason::Unknown))
+            }
+            Err(e) => match on_error {
+                OnParseError::Fail => {
+                    anyhow::bail!("Invalid {} entry [{}]: {} ({})", section, idx, item, e)
+                }
+                OnParseError::Skip => {
+                    log::warn!(
+                        "Skipping invalid {} entry [{}]: {} ({})",
+                        section,
+                        idx,
+                        item,
+                        e
+                    )
+                }
+            },
+        }
+    }
+    Ok(entries)
+}
+
+impl BlacklistState {
+    /// Counts strike for Node, which didn't pay for Agreement and blacklists it
+    /// after reaching `strikes_before_blacklist` and `min_unpaid_amount`.
+    /// Returns true, if Node was added to blacklist. `decision_id` correlates log lines
+    /// related to the same enforcement decision. `now` is the time of the default.
+    pub(crate) fn add_strike(
+        &mut self,
+        record: &TrackedAgreement,
+        reason: BlacklistReason,
+        decision_id: &str,
+        now: DateTime<Utc>,
+        config: &BlacklistReputationsConfig,
+    ) -> bool {
+        self.count_strike(record, reason, decision_id, now, config)
+            .blacklisted
+    }
+
+    /// The same as `add_strike`, but tells also whether strike was counted, so it can be
+    /// reverted later.
+    pub(crate) fn count_strike(
+        &mut self,
+        record: &TrackedAgreement,
+        reason: BlacklistReason,
+        decision_id: &str,
+        now: DateTime<Utc>,
+        config: &BlacklistReputationsConfig,
+    ) -> Strike {
+        let node = record.node;
+        let amount = record.amount_due();
+        let first_offense = self
+            .stats
+            .get(&node)
+            .map(|stats| stats.paid == 0 && stats.defaulted == 0)
+            .unwrap_or(true);
+
+        let stats = self.stats.entry(node).or_default();
+        stats.defaulted += 1;
+        stats.last_default = Some(now);
+        let stats = stats.clone();
+
+        if self.whitelist.contains(&node) {
+            log::info!(
+                decision_id = decision_id;
+                "Node [{}] is whitelisted. Not counting strike.",
+                node
+            );
+            return Strike::default();
+        }
+
+        let first_offense_action = if first_offense {
+            config.first_offense_action
+        } else {
+            FirstOffenseAction::Normal
+        };
+        if first_offense_action == FirstOffenseAction::Forgive {
+            log::info!(
+                decision_id = decision_id;
+                "First default of Node [{}] (agreement [{}]) forgiven. Not counting strike.",
+                node,
+                record.id
+            );
+            return Strike::default();
+        }
+
+        self.paid_streak.remove(&node);
+        let weight = config.strike_weight(&reason);
+        let strikes = self.strikes.entry(node).or_insert(0);
+        *strikes += weight;
+        let counted = Strike {
+            weight: Some(weight),
+            blacklisted: false,
+        };
+        let strikes = *strikes;
+
+        let unpaid = self.unpaid.entry(node).or_insert(0.0);
+        *unpaid += amount.unwrap_or(0.0);
+        let unpaid = *unpaid;
+
+        log::info!(
+            event = "strike",
+            decision_id = decision_id,
+            node:% = node,
+            agreement_id = record.id.as_str(),
+            reason:% = reason,
+            unpaid = unpaid;
+            "Strike for Node [{}], agreement [{}]: {}. Node owes {} in total.",
+            node,
+            record.id,
+            reason,
+            unpaid
+        );
+
+        self.penalize(node, config);
+        self.graylist.insert(node, now);
+
+        let immediate = first_offense_action == FirstOffenseAction::Blacklist;
+        if !immediate && config.min_unpaid_amount > 0.0 && unpaid <= config.min_unpaid_amount {
+            log::info!(
+                decision_id = decision_id;
+                "Node [{}] owes {} (amount of last agreement: {:?}), which doesn't exceed {}.",
+                node,
+                unpaid,
+                amount,
+                config.min_unpaid_amount
+            );
+            return counted;
+        }
+
+        let required = config.strikes_required(&stats, record.policy.as_deref(), now);
+        let exceeded = if immediate {
+            log::info!(
+                decision_id = decision_id;
+                "First default of Node [{}], which has no payment history.",
+                node
+            );
+            true
+        } else if config.default_rate_threshold > 0.0 {
+            if stats.total < config.min_samples {
+                log::info!(
+                    decision_id = decision_id;
+                    "Node [{}] has only {}/{} agreements. Not enough to judge default rate.",
+                    node,
+                    stats.total,
+                    config.min_samples
+                );
+                return counted;
+            }
+            log::info!(
+                decision_id = decision_id;
+                "Node [{}] default rate: {:.3} ({}/{}), threshold: {}.",
+                node,
+                stats.default_rate(),
+                stats.defaulted,
+                stats.total,
+                config.default_rate_threshold
+            );
+            stats.default_rate() > config.default_rate_threshold
+        } else {
+            strikes >= required
+        };
+
+        if exceeded {
+            if config.dry_run {
+                log::info!(
+                    decision_id = decision_id;
+                    "[Dry run] Node [{}] has {} strikes. Would be blacklisted.",
+                    node,
+                    strikes
+                );
+                return counted;
+            }
+
+            log::info!(
+                event = "blacklist_applied",
+                decision_id = decision_id,
+                node:% = node;
+                "Node [{}] has {} strikes. Blacklisting..",
+                node,
+                strikes
+            );
+            self.graylist.remove(&node);
+            let mut entry = BlacklistEntry::new(node, reason);
+            entry.added = now;
+            self.add_entry(
+                entry
+                    .with_agreement(&record.id)
+                    .with_policy(record.policy.clone())
+                    .with_endpoint(record.endpoint.clone()),
+                config,
+            );
+            Strike {
+                blacklisted: true,
+                ..counted
+            }
+        } else {
+            log::info!(
+                decision_id = decision_id;
+                "Node [{}] has {}/{} strikes.",
+                node,
+                strikes,
+                required
+            );
+            counted
+        }
+    }
+
+    /// Inserts entries restored from snapshot or imported by operator. Unlike `add_entry`
+    /// these aren't new bans, so listener isn't notified and `max_blacklist_size` isn't
+    /// enforced. Already blacklisted Nodes keep their entries. Returns number of inserted entries.
+    fn insert_entries(&mut self, entries: impl IntoIterator<Item = BlacklistEntry>) -> usize {
+        let mut inserted = 0;
+        for entry in entries {
+            if let Entry::Vacant(vacant) = self.blacklist.entry(entry.node) {
+                if let Some(backend) = &self.backend {
+                    backend
+                        .add_to_blacklist(&entry)
+                        .map_err(|e| log::warn!("Failed to store blacklist entry: {}", e))
+                        .ok();
+                }
+                vacant.insert(entry);
+                inserted += 1;
+            }
+        }
+        self.update_blacklist_size();
+        inserted
+    }
+
+    /// Adds Node to blacklist and notifies listener. Sending event never blocks.
+    fn add_entry(&mut self, entry: BlacklistEntry, config: &BlacklistReputationsConfig) {
+        log::info!(
+            event = "blacklisted",
+            node:% = entry.node,
+            agreement_id = entry.agreement_id.as_deref().unwrap_or_default(),
+            reason:% = entry.reason;
+            "Node [{}] added to blacklist: {}",
+            entry.node,
+            entry.reason
+        );
+
+        let event = BlacklistEvent {
+            node: entry.node,
+            reason: entry.reason.clone(),
+            agreement_id: entry.agreement_id.clone(),
+            timestamp: entry.added,
+        };
+
+        let entry = self.blacklist.entry(entry.node).or_insert(entry);
+        if let Some(backend) = &self.backend {
+            backend
+                .add_to_blacklist(entry)
+                .map_err(|e| log::warn!("Failed to store blacklist entry: {}", e))
+                .ok();
+        }
+        if let Some(max_size) = config.max_blacklist_size {
+            self.evict_oldest(max_size);
+        }
+        self.update_blacklist_size();
+
+        if let Some(sender) = &self.events {
+            match sender.try_send(event) {
+                Ok(()) => (),
+                Err(mpsc::error::TrySendError::Full(event)) => {
+                    log::warn!(
+                        "Blacklist events channel full. Dropping event for Node [{}].",
+                        event.node
+                    );
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => {
+                    log::debug!("Blacklist events receiver closed.");
+                    self.events = None;
+                }
+            }
+        }
+    }
+
+    /// Removes Node from blacklist.
+    pub(crate) fn remove_entry(&mut self, node: &NodeId) -> Option<BlacklistEntry> {
+        let entry = self.blacklist.remove(node)?;
+        log::info!(
+            event = "unblacklisted",
+            node:% = node;
+            "Node [{}] removed from blacklist.",
+            node
+        );
+        self.remote.remove(node);
+        if let Some(backend) = &self.backend {
+            backend
+                .remove_from_blacklist(node)
+                .map_err(|e| log::warn!("Failed to remove blacklist entry: {}", e))
+                .ok();
+        }
+        self.update_blacklist_size();
+        Some(entry)
+    }
+
+    /// Adds Nodes from remote blacklist, which aren't blacklisted locally yet and removes
+    /// Nodes, which disappeared from remote blacklist. Locally blacklisted Nodes are kept.
+    /// Returns number of added and removed Nodes.
+    fn merge_remote(&mut self, remote: HashMap<NodeId, BlacklistEntry>) -> (usize, usize) {
+        let removed = self
+            .remote
+            .iter()
+            .filter(|node| !remote.contains_key(node))
+            .cloned()
+            .collect::<Vec<_>>();
+        for node in &removed {
+            self.remove_entry(node);
+        }
+
+        let mut added = 0;
+        for (node, entry) in remote {
+            if let Entry::Vacant(vacant) = self.blacklist.entry(node) {
+                vacant.insert(entry);
+                self.remote.insert(node);
+                added += 1;
+            }
+        }
+        self.update_blacklist_size();
+        (added, removed.len())
+    }
+
+    /// Merges imported entries with blacklist. Returns number of added and removed Nodes.
+    /// Imported entries aren't new bans, so listener isn't notified about them.
+    fn merge_entries(&mut self, entries: Vec<BlacklistEntry>, mode: MergeMode) -> (usize, usize) {
+        let local = self
+            .blacklist
+            .keys()
+            .filter(|node| !self.remote.contains(node))
+            .cloned()
+            .collect::<HashSet<_>>();
+        let imported = entries
+            .iter()
+            .map(|entry| entry.node)
+            .collect::<HashSet<_>>();
+        let (existing, new): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .partition(|entry| local.contains(&entry.node));
+
+        let mut removed = 0;
+        if mode == MergeMode::Replace {
+            for node in local.difference(&imported) {
+                self.remove_entry(node);
+                removed += 1;
+            }
+            // Imported entries replace existing ones, even for the same Node.
+            for entry in &existing {
+                self.blacklist.remove(&entry.node);
+            }
+            self.insert_entries(existing);
+        }
+        let added = self.insert_entries(new);
+        (added, removed)
+    }
+
+    /// Remembers proposal sent to Node, so it can be matched when Node rejects it.
+    fn track_proposal(&mut self, id: &str, node: NodeId, now: DateTime<Utc>) {
+        let oldest = now - chrono::Duration::seconds(PROPOSAL_TRACKING_SECS);
+        self.proposals.retain(|_, (_, sent)| *sent > oldest);
+        self.proposals.insert(id.to_string(), (node, now));
+    }
+
+    /// Counts negotiation abandoned by Node at `now`. Returns true, if Node was blacklisted.
+    fn proposal_abandoned(
+        &mut self,
+        proposal_id: &str,
+        now: DateTime<Utc>,
+        config: &BlacklistReputationsConfig,
+    ) -> bool {
+        let node = match self.proposals.remove(proposal_id) {
+            Some((node, _)) => node,
+            None => return false,
+        };
+        // Both our and Node's proposal from the same negotiation step could be tracked.
+        self.proposals.retain(|_, (other, _)| *other != node);
+
+        self.seen(node, now);
+        self.stats.entry(node).or_default().abandoned += 1;
+        if config.abandon_threshold == 0 || self.whitelist.contains(&node) {
+            return false;
+        }
+
+        let streak = self.abandon_streak.entry(node).or_insert(0);
+        *streak += 1;
+        let streak = *streak;
+        if streak < config.abandon_threshold {
+            log::debug!(
+                "Node [{}] abandoned {}/{} negotiations.",
+                node,
+                streak,
+                config.abandon_threshold
+            );
+            return false;
+        }
+
+        if config.dry_run {
+            log::info!(
+                "[Dry run] Node [{}] abandoned {} negotiations. Would penalize it.",
+                node,
+                streak
+            );
+            return false;
+        }
+
+        if !config.abandon_blacklist {
+            log::info!(
+                "Node [{}] abandoned {} negotiations. Graylisting..",
+                node,
+                streak
+            );
+            self.graylist.insert(node, now);
+            return false;
+        }
+
+        log::info!(
+            "Node [{}] abandoned {} negotiations. Blacklisting..",
+            node,
+            streak
+        );
+        self.abandon_streak.remove(&node);
+        let mut entry = BlacklistEntry::new(node, BlacklistReason::AbandonedNegotiations);
+        entry.added = now;
+        self.add_entry(entry, config);
+        true
+    }
+
+    /// Records interaction with Node.
+    fn seen(&mut self, node: NodeId, now: DateTime<Utc>) {
+        let stats = self.stats.entry(node).or_default();
+        stats.first_seen.get_or_insert(now);
+        stats.last_seen = Some(now);
+    }
+
+    /// Records proposal from Node. Only Nodes with history are updated.
+    fn seen_proposal(&mut self, node: NodeId, now: DateTime<Utc>) {
+        if let Some(stats) = self.stats.get_mut(&node) {
+            stats.last_seen = Some(now);
+        }
+    }
+
+    /// Removes the oldest entries until blacklist fits in `max_size`.
+    fn evict_oldest(&mut self, max_size: usize) {
+        while self.blacklist.len() > max_size {
+            let oldest = match self.blacklist.values().min_by_key(|entry| entry.added) {
+                Some(entry) => entry.node,
+                None => return,
+            };
+            log::info!(
+                "Blacklist exceeds {} entries. Evicting the oldest entry for Node [{}]..",
+                max_size,
+                oldest
+            );
+            self.remove_entry(&oldest);
+        }
+    }
+
+    fn track(&mut self, record: TrackedAgreement) {
+        let agreement_id = record.id.clone();
+        self.agreements.insert(agreement_id.clone(), record);
+        self.store_agreement(&agreement_id);
+    }
+
+    /// Writes changes of tracked Agreement to backend.
+    fn store_agreement(&self, agreement_id: &str) {
+        if let (Some(backend), Some(record)) = (&self.backend, self.agreements.get(agreement_id)) {
+            backend
+                .track_agreement(record)
+                .map_err(|e| log::warn!("Failed to store agreement [{}]: {}", agreement_id, e))
+                .ok();
+        }
+    }
+
+    /// Timers and event handlers give strike only for Agreement they untracked under
+    /// state lock, so each default is counted once, even if they race.
+    fn untrack(&mut self, agreement_id: &str) -> Option<TrackedAgreement> {
+        let record = self.agreements.remove(agreement_id)?;
+        if let Some(backend) = &self.backend {
+            backend
+                .remove_agreement(agreement_id)
+                .map_err(|e| log::warn!("Failed to remove agreement [{}]: {}", agreement_id, e))
+                .ok();
+        }
+        Some(record)
+    }
+
+    /// Recomputes payment timeout from payment latencies of all Nodes.
+    fn update_auto_timeout(&mut self, config: &BlacklistReputationsConfig) {
+        let mut latency = PaymentLatency::default();
+        for stats in self.stats.values() {
+            latency.merge(&stats.payment_latency);
+        }
+        if latency.samples < AUTO_TIMEOUT_MIN_SAMPLES {
+            log::debug!(
+                "Only {}/{} payments observed. Not adjusting payment timeout.",
+                latency.samples,
+                AUTO_TIMEOUT_MIN_SAMPLES
+            );
+            return;
+        }
+
+        let percentile = match latency.percentile_secs(config.auto_timeout_percentile) {
+            Some(percentile) => std::time::Duration::from_secs_f64(percentile),
+            None => return,
+        };
+        let mut timeout = percentile + config.auto_timeout_margin;
+        if let Some(max) = config.auto_timeout_max {
+            timeout = timeout.min(max);
+        }
+
+        if self.auto_timeout != Some(timeout) {
+            log::info!(
+                "Payment timeout adjusted to {} ({}th percentile of {} payments: {}).",
+                humantime::format_duration(timeout),
+                config.auto_timeout_percentile,
+                latency.samples,
+                humantime::format_duration(percentile)
+            );
+            self.auto_timeout = Some(timeout);
+        }
+    }
+
+    /// Stops waiting for payment of Agreements terminated by Node, so their timers
+    /// won't blacklist Node again after operator removed it from blacklist.
+    /// Returns number of forgiven Agreements.
+    pub(crate) fn forgive_pending(&mut self, node: &NodeId) -> usize {
+        let pending = self
+            .agreements
+            .values()
+            .filter(|record| record.node == *node && record.terminated.is_some())
+            .map(|record| record.id.clone())
+            .collect::<Vec<_>>();
+        for agreement_id in &pending {
+            if let Some(mut record) = self.untrack(agreement_id) {
+                record.stop_timer();
+            }
+        }
+        self.late.retain(|_, late| late.node != *node);
+
+        if !pending.is_empty() {
+            log::info!(
+                "Forgiven {} unpaid agreements of Node [{}] removed from blacklist.",
+                pending.len(),
+                node
+            );
+        }
+        pending.len()
+    }
+
+    /// Stops tracking Agreements, which weren't terminated in `max_age` after signing,
+    /// because termination event was probably missed or Agreement expired. With
+    /// `strike_expired_agreements` Nodes get strike for each of them. Returns true,
+    /// if any Node was blacklisted.
+    fn remove_stale_agreements(
+        &mut self,
+        now: DateTime<Utc>,
+        max_age: std::time::Duration,
+        config: &BlacklistReputationsConfig,
+    ) -> bool {
+        let max_age = match chrono::Duration::from_std(max_age) {
+            Ok(max_age) => max_age,
+            Err(_) => return false,
+        };
+        let stale = self
+            .agreements
+            .values()
+            .filter(|record| record.terminated.is_none() && now - record.signed > max_age)
+            .map(|record| record.id.clone())
+            .collect::<Vec<_>>();
+
+        let mut blacklisted = false;
+        for agreement_id in &stale {
+            if let Some(record) = self.untrack(agreement_id) {
+                log::info!(
+                    "Agreement [{}] with node [{}] signed at {} wasn't terminated. Not tracking it anymore.",
+                    agreement_id,
+                    record.node,
+                    record.signed.to_rfc3339()
+                );
+                if config.strike_expired_agreements {
+                    let decision_id = Uuid::new_v4().to_string();
+                    blacklisted |= self.add_strike(
+                        &record,
+                        BlacklistReason::AgreementExpired,
+                        &decision_id,
+                        now,
+                        config,
+                    );
+                }
+            }
+        }
+        blacklisted
+    }
+
+    /// Stops tracking terminated Agreements older than `max_terminated_age`, when
+    /// payment timers are disabled. Without timer nothing else would remove Agreements,
+    /// which never got invoice event. Returns number of removed Agreements.
+    fn remove_untimed_agreements(
+        &mut self,
+        now: DateTime<Utc>,
+        config: &BlacklistReputationsConfig,
+    ) -> usize {
+        let max_age = match config
+            .untimed_agreement_age()
+            .and_then(|max_age| chrono::Duration::from_std(max_age).ok())
+        {
+            Some(max_age) => max_age,
+            None => return 0,
+        };
+        let stale = self
+            .agreements
+            .values()
+            .filter(|record| matches!(record.terminated, Some(terminated) if now - terminated > max_age))
+            .map(|record| record.id.clone())
+            .collect::<Vec<_>>();
+
+        for agreement_id in &stale {
+            if let Some(record) = self.untrack(agreement_id) {
+                log::info!(
+                    "Agreement [{}] with node [{}] terminated at {} got no invoice event. Not tracking it anymore.",
+                    agreement_id,
+                    record.node,
+                    record.terminated.unwrap_or(record.signed).to_rfc3339()
+                );
+                self.record_outcome(&record, None, None, config);
+            }
+        }
+        stale.len()
+    }
+
+    /// Counts Agreement paid on time. Blacklisted Node is removed from blacklist after
+    /// `redemption_payments` consecutive payments, unless it was banned manually.
+    /// Returns true, if Node was redeemed.
+    pub(crate) fn add_payment(
+        &mut self,
+        node: NodeId,
+        now: DateTime<Utc>,
+        config: &BlacklistReputationsConfig,
+    ) -> bool {
+        self.stats.entry(node).or_default().paid += 1;
+
+        let streak = self.paid_streak.entry(node).or_insert(0);
+        *streak += 1;
+        let streak = *streak;
+
+        // Operator's bans can't be paid off.
+        match self.blacklist.get(&node) {
+            Some(entry) if config.redemption_payments > 0 && !entry.is_manual() => (),
+            _ => return false,
+        }
+
+        if streak < config.redemption_payments {
+            log::info!(
+                "Blacklisted Node [{}] paid {}/{} agreements needed for redemption.",
+                node,
+                streak,
+                config.redemption_payments
+            );
+            return false;
+        }
+
+        log::info!(
+            "Node [{}] paid {} consecutive agreements. Removing from blacklist..",
+            node,
+            streak
+        );
+        self.remove_entry(&node);
+        self.strikes.remove(&node);
+        self.unpaid.remove(&node);
+        self.paid_streak.remove(&node);
+        self.stats.entry(node).or_default().last_redeemed = Some(now);
+        true
+    }
+
+    fn record_latency(&mut self, node: NodeId, terminated: DateTime<Utc>, paid: DateTime<Utc>) {
+        let latency = (paid - terminated).to_std().unwrap_or_default();
+        self.stats
+            .entry(node)
+            .or_default()
+            .payment_latency
+            .record(latency);
+    }
+
+    /// Remembers outcome of Agreement, which is no longer tracked, if enabled
+    /// by `record_outcomes`.
+    pub(crate) fn record_outcome(
+        &mut self,
+        record: &TrackedAgreement,
+        paid: Option<DateTime<Utc>>,
+        rejected: Option<DateTime<Utc>>,
+        config: &BlacklistReputationsConfig,
+    ) {
+        if !config.record_outcomes {
+            return;
+        }
+        self.history.push(AgreementOutcome {
+            id: record.id.clone(),
+            node: record.node,
+            signed: record.signed,
+            terminated: record.terminated,
+            paid,
+            rejected,
+            amount: record.amount_due.or_else(|| record.amount_due()),
+            amount_paid: record.amount_paid,
+            policy: record.policy.clone(),
+        });
+    }
+
+    /// Remembers Agreement, which wasn't paid in time at `now`, for `payment_grace` period.
+    fn add_late(
+        &mut self,
+        record: &TrackedAgreement,
+        strike: Strike,
+        now: DateTime<Utc>,
+        deadline: DateTime<Utc>,
+    ) {
+        self.late.retain(|_, late| late.deadline > now);
+        self.late.insert(
+            record.id.clone(),
+            LateAgreement {
+                node: record.node,
+                signed: record.signed,
+                policy: record.policy.clone(),
+                amount: record.amount_due(),
+                deadline,
+                terminated: record.terminated,
+                strike,
+            },
+        );
+    }
+
+    /// Reverts strike counted for Agreement paid within `payment_grace` after deadline.
+    /// Returns true, if Node was removed from blacklist.
+    fn undo_late_strike(
+        &mut self,
+        agreement_id: &str,
+        now: DateTime<Utc>,
+        config: &BlacklistReputationsConfig,
+    ) -> bool {
+        let late = match self.late.remove(agreement_id) {
+            Some(late) => late,
+            None => return false,
+        };
+        let node = late.node;
+        if let Some(terminated) = late.terminated {
+            self.record_latency(node, terminated, now);
+        }
+        if config.record_outcomes {
+            // Replaces outcome recorded on payment timeout.
+            self.history.push(AgreementOutcome {
+                id: agreement_id.to_string(),
+                node,
+                signed: late.signed,
+                terminated: late.terminated,
+                paid: Some(now),
+                rejected: None,
+                amount: late.amount,
+                amount_paid: None,
+                policy: late.policy.clone(),
+            });
+        }
+
+        if now > late.deadline {
+            log::info!(
+                "Node [{}] paid agreement [{}] after grace period.",
+                node,
+                agreement_id
+            );
+            return false;
+        }
+
+        if let Some(stats) = self.stats.get_mut(&node) {
+            stats.defaulted = stats.defaulted.saturating_sub(1);
+        }
+
+        let weight = match late.strike.weight {
+            Some(weight) => weight,
+            None => {
+                log::info!(
+                    "Node [{}] paid agreement [{}] within grace period. No strike was counted.",
+                    node,
+                    agreement_id
+                );
+                return self.add_payment(node, now, config);
+            }
+        };
+        log::info!(
+            "Node [{}] paid agreement [{}] within grace period. Undoing strike..",
+            node,
+            agreement_id
+        );
+
+        if let Some(strikes) = self.strikes.get_mut(&node) {
+            *strikes = strikes.saturating_sub(weight);
+            if *strikes == 0 {
+                self.strikes.remove(&node);
+                self.graylist.remove(&node);
+            }
+        }
+        if let (Some(unpaid), Some(amount)) = (self.unpaid.get_mut(&node), late.amount) {
+            *unpaid -= amount;
+            if *unpaid <= 0.0 {
+                self.unpaid.remove(&node);
+            }
+        }
+        if config.reputation_penalty > 0.0 {
+            if let Some(factor) = self.reputation.get_mut(&node) {
+                *factor = (*factor / config.reputation_penalty).min(1.0);
+            }
+        }
+
+        let unblacklisted = late.strike.blacklisted && self.remove_entry(&node).is_some();
+        let redeemed = self.add_payment(node, now, config);
+        unblacklisted || redeemed
+    }
+
+    /// Lowers reputation of Node, which didn't pay for Agreement.
+    fn penalize(&mut self, node: NodeId, config: &BlacklistReputationsConfig) {
+        let factor = self.reputation.entry(node).or_insert(1.0);
+        *factor *= config.reputation_penalty;
+
+        log::debug!(
+            "Node [{}] reputation factor lowered to {:.3}.",
+            node,
+            factor
+        );
+    }
+
+    /// Moves reputation factors of all Nodes back toward 1.0.
+    fn recover_reputation(&mut self, recovery: f64) {
+        for factor in self.reputation.values_mut() {
+            *factor = (*factor + recovery).min(1.0);
+        }
+        self.reputation.retain(|_, factor| *factor < 1.0);
+    }
+
+    pub(crate) fn update_blacklist_size(&self) {
+        self.metrics.blacklist_size.set(self.blacklist.len() as i64);
+    }
+
+    /// Finds blacklist entry for Node. Entries older than `blacklist_ttl` are removed.
+    /// Entries from `static_blacklist` never expire.
+    fn find_blacklisted(
+        &mut self,
+        node: &NodeId,
+        now: DateTime<Utc>,
+        config: &BlacklistReputationsConfig,
+    ) -> Option<&BlacklistEntry> {
+        if self.static_blacklist.contains_key(node) {
+            return self.static_blacklist.get(node);
+        }
+
+        let entry = self.blacklist.get(node)?;
+        if entry.is_expired_at(config.blacklist_ttl_for(entry), now) {
+            log::info!("Blacklist entry for Node [{}] expired. Removing..", node);
+            self.remove_entry(node);
+            return None;
+        }
+        self.blacklist.get(node)
+    }
+
+    /// Entries of local blacklist, which expire before `until`, sorted by expiration.
+    /// Remote entries are excluded, since they are removed by remote source.
+    fn expiring(
+        &self,
+        until: DateTime<Utc>,
+        config: &BlacklistReputationsConfig,
+    ) -> Vec<(&BlacklistEntry, DateTime<Utc>)> {
+        let mut expiring = self
+            .blacklist
+            .values()
+            .filter(|entry| !self.remote.contains(&entry.node))
+            .filter_map(|entry| {
+                entry
+                    .expires(config.blacklist_ttl_for(entry))
+                    .map(|expires| (entry, expires))
+            })
+            .filter(|(_, expires)| *expires <= until)
+            .collect::<Vec<_>>();
+        expiring.sort_by_key(|(_, expires)| *expires);
+        expiring
+    }
+
+    /// Removes entries expired at `now` from blacklist. Returns number of removed entries.
+    pub(crate) fn remove_expired(
+        &mut self,
+        now: DateTime<Utc>,
+        config: &BlacklistReputationsConfig,
+    ) -> usize {
+        let expired = self
+            .blacklist
+            .values()
+            .filter(|entry| entry.is_expired_at(config.blacklist_ttl_for(entry), now))
+            .map(|entry| entry.node)
+            .collect::<Vec<_>>();
+        for node in &expired {
+            self.remove_entry(node);
+        }
+        expired.len()
+    }
+
+    /// Checks if Node is on graylist. Entries older than `graylist_ttl` are removed.
+    fn is_graylisted(
+        &mut self,
+        node: &NodeId,
+        now: DateTime<Utc>,
+        config: &BlacklistReputationsConfig,
+    ) -> bool {
+        let added = match self.graylist.get(node) {
+            Some(added) => *added,
+            None => return false,
+        };
+        if let Some(ttl) = config.graylist_ttl {
+            if expired(added, ttl, now) {
+                log::info!("Graylist entry for Node [{}] expired. Removing..", node);
+                self.graylist.remove(node);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Score factor of Node. With `decay` configured it depends only on time
+    /// of last default, otherwise penalties are recovered periodically.
+    fn reputation_factor(
+        &self,
+        node: &NodeId,
+        now: DateTime<Utc>,
+        config: &BlacklistReputationsConfig,
+    ) -> f64 {
+        match &config.decay {
+            Some(decay) => self
+                .stats
+                .get(node)
+                .and_then(|stats| stats.last_default)
+                .map(|last_default| current_factor(last_default, now, decay))
+                .unwrap_or(1.0),
+            None => self.reputation.get(node).cloned().unwrap_or(1.0),
+        }
+    }
+}
+
+fn expired(timestamp: DateTime<Utc>, ttl: std::time::Duration, now: DateTime<Utc>) -> bool {
+    match chrono::Duration::from_std(ttl) {
+        Ok(ttl) => now - timestamp > ttl,
+        Err(_) => false,
+    }
+}
+
+/// Writes blacklist to store. With `debounce` set, writes requested within the window
+/// are coalesced into one write at it's end.
+#[derive(Clone)]
+struct BlacklistWriter {
+    store: Arc<dyn ReputationStore>,
+    state: Arc<Mutex<BlacklistState>>,
+    handle: Handle,
+    debounce: Option<std::time::Duration>,
+    /// Scheduled write, which wasn't done yet.
+    pending: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl BlacklistWriter {
+    /// Requests write of blacklist. `state` is the locked `self.state`.
+    fn persist(&self, state: &BlacklistState) -> anyhow::Result<()> {
+        let debounce = match self.debounce {
+            Some(debounce) => debounce,
+            None => return self.store.persist_blacklist(state),
+        };
+        let mut pending = lock(&self.pending);
+        if pending.is_some() {
+            return Ok(());
+        }
+
+        let writer = self.clone();
+        *pending = Some(self.handle.spawn(async move {
+            tokio::time::sleep(debounce).await;
+            // Changes made from now on need another write.
+            lock(&writer.pending).take();
+            if let Err(e) = writer.store.persist_blacklist(&lock(&writer.state)) {
+                log::warn!("Failed to persist blacklist: {}", e);
+            }
+        }));
+        Ok(())
+    }
+
+    /// Cancels scheduled write. Used on shutdown, which writes whole state anyway.
+    fn cancel(&self) {
+        let pending = lock(&self.pending).take();
+        if let Some(pending) = pending {
+            pending.abort();
+        }
+    }
+}
+
+/// Writes state together with Agreement outcomes recorded since previous write.
+/// Outcomes are kept in state, if they can't be appended to history.
+fn persist_with_history(
+    store: &dyn ReputationStore,
+    state: &mut BlacklistState,
+) -> anyhow::Result<()> {
+    store.persist(state)?;
+    if !state.history.is_empty() {
+        store.append_history(&state.history)?;
+        state.history.clear();
+    }
+    Ok(())
+}
+
+/// Fetches remote blacklist and merges it with state. Failures are only logged,
+/// local blacklist is used in that case. Changed blacklist is cached in `store`.
+async fn sync_remote(
+    remote: &mut RemoteBlacklist,
+    state: &Mutex<BlacklistState>,
+    store: &dyn ReputationStore,
+) {
+    match remote.fetch().await {
+        Ok(Some(blacklist)) => {
+            if let Some(cache) = remote.cache() {
+                if let Err(e) = store.persist_remote_cache(cache) {
+                    log::warn!("Failed to cache remote blacklist: {}", e);
+                }
+            }
+
+            let mut state = lock(state);
+            state.metrics.remote_cache_misses.inc();
+            let (added, removed) = state.merge_remote(blacklist);
+            state.last_remote_sync = Some(Utc::now());
+            log::info!(
+                "Synchronized remote blacklist {}. Added {} Nodes, removed {} Nodes.",
+                remote.url(),
+                added,
+                removed
+            );
+        }
+        Ok(None) => {
+            let mut state = lock(state);
+            state.metrics.remote_cache_hits.inc();
+            state.last_remote_sync = Some(Utc::now());
+            log::debug!("Remote blacklist {} not modified.", remote.url());
+        }
+        Err(e) => log::warn!(
+            "Failed to fetch remote blacklist from {}: {}. Using local blacklist only.",
+            remote.url(),
+            e
+        ),
+    }
+}
+
+/// `/approved_date` is set by counterparty, whose clock can be skewed. Dates further
+/// in the future than `max_clock_skew` are replaced by local time.
+fn clamp_signed(
+    agreement_id: &str,
+    signed: DateTime<Utc>,
+    now: DateTime<Utc>,
+    config: &BlacklistReputationsConfig,
+) -> DateTime<Utc> {
+    let skew = chrono::Duration::from_std(config.max_clock_skew)
+        .unwrap_or_else(|_| chrono::Duration::zero());
+    if signed > now + skew {
+        log::warn!(
+            "Agreement [{}] approved date {} is in the future. Using local time {}.",
+            agreement_id,
+            signed.to_rfc3339(),
+            now.to_rfc3339()
+        );
+        return now;
+    }
+    signed
+}
+
+/// Payment platforms proposed by requestor. Platform chosen by requestor takes precedence
+/// over list of platforms, for which requestor declared addresses.
+fn payment_platforms(properties: &serde_json::Value) -> Vec<String> {
+    if let Some(platform) = properties
+        .pointer("/golem/com/payment/chosen-platform")
+        .and_then(|platform| platform.as_str())
+    {
+        return vec![platform.to_string()];
+    }
+
+    properties
+        .pointer("/golem/com/payment/platform")
+        .and_then(|platforms| platforms.as_object())
+        .map(|platforms| platforms.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Inserts property under nested `path`, creating missing objects on the way.
+/// Existing values are never overwritten.
+fn insert_property(properties: &mut serde_json::Value, path: &[&str], value: serde_json::Value) {
+    let (name, parents) = match path.split_last() {
+        Some(split) => split,
+        None => return,
+    };
+
+    let mut object = properties;
+    for parent in parents {
+        object = match object {
+            serde_json::Value::Object(map) => map
+                .entry(parent.to_string())
+                .or_insert_with(|| serde_json::json!({})),
+            _ => return,
+        };
+    }
+
+    if let serde_json::Value::Object(map) = object {
+        map.entry(name.to_string()).or_insert(value);
+    }
+}
+
+/// Disables enforcement while kill switch file exists. Enforcement disabled in config
+/// (`disabled`) can't be enabled by removing the file.
+fn watch_kill_switch(
+    path: PathBuf,
+    disabled: bool,
+    state: Arc<Mutex<BlacklistState>>,
+) -> notify::Result<RecommendedWatcher> {
+    let directory = path
+        .parent()
+        .map(|directory| directory.to_path_buf())
+        .unwrap_or_default();
+    let apply = move |path: &Path| {
+        let switched = path.exists();
+        let mut state = lock(&state);
+        if state.enforcement_disabled != (disabled || switched) {
+            state.enforcement_disabled = disabled || switched;
+            if switched {
+                log::warn!(
+                    "Kill switch {} found. Blacklisted Nodes won't be rejected.",
+                    path.display()
+                );
+            } else {
+                log::warn!(
+                    "Kill switch {} removed. Rejecting blacklisted Nodes again.",
+                    path.display()
+                );
+            }
+        }
+    };
+    apply(&path);
+
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event)
+                if event
+                    .paths
+                    .iter()
+                    .any(|p| p.file_name() == path.file_name()) =>
+            {
+                apply(&path)
+            }
+            Ok(_) => (),
+            Err(e) => log::warn!("Error watching kill switch: {}", e),
+        })?;
+
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+/// Watches directory containing blacklist file for changes of the file. Directory is watched
+/// instead of the file, because atomic writes replace the file.
+fn watch_blacklist(
+    store: Arc<FileStore>,
+    state: Arc<Mutex<BlacklistState>>,
+) -> notify::Result<RecommendedWatcher> {
+    let path = store.files().path("blacklist");
+    let directory = match path.parent() {
+        Some(directory) => directory.to_path_buf(),
+        None => store.files().workdir().to_path_buf(),
+    };
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                log::warn!("Error watching blacklist file: {}", e);
+                return;
+            }
+        };
+
+        let blacklist_changed = event
+            .paths
+            .iter()
+            .any(|changed| changed.file_name() == path.file_name());
+        if !blacklist_changed || !(event.kind.is_create() || event.kind.is_modify()) {
+            return;
+        }
+
+        if let Err(e) = store.merge_external(&mut lock(&state)) {
+            log::warn!("Failed to reload edited blacklist: {}", e);
+        }
+    })?;
+
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+impl NegotiatorConstructor<BlacklistReputation> for BlacklistReputation {
+    fn new(
+        name: &str,
+        config: serde_yaml::Value,
+        working_dir: PathBuf,
+    ) -> anyhow::Result<BlacklistReputation> {
+        let config = with_env_overrides(name, config, std::env::vars())?;
+        let config: BlacklistReputationsConfig = serde_yaml::from_value(config)?;
+
+        // Agent may not create plugin directory before first run.
+        let created = !working_dir.exists();
+        std::fs::create_dir_all(&working_dir).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to create working directory {}: {}",
+                working_dir.display(),
+                e
+            )
+        })?;
+
+        logging::start_logger(
+            &working_dir,
+            if name.is_empty() {
+                "blacklist".to_string()
+            } else {
+                format!("blacklist-{}", name)
+            },
+            config.log_target,
+            config.log_format,
+            Rotation {
+                max_size: config.log_max_size,
+                keep_files: config.log_keep_files,
+            },
+        )?;
+        if created {
+            log::info!("Created working directory {}.", working_dir.display());
+        }
+
+        BlacklistReputationBuilder::from_config(config)
+            .name(name)
+            .build(working_dir)
+    }
+}
+
+/// Prefix of environment variables overriding config of negotiator instance `name`.
+pub fn env_prefix(name: &str) -> String {
+    if name.is_empty() {
+        "REPUTATION_".to_string()
+    } else {
+        format!("REPUTATION_{}_", name.to_uppercase().replace('-', "_"))
+    }
+}
+
+/// Overrides fields of YAML config with environment variables of instance `name`.
+fn with_env_overrides(
+    name: &str,
+    config: serde_yaml::Value,
+    vars: impl Iterator<Item = (String, String)>,
+) -> anyhow::Result<serde_yaml::Value> {
+    let mut config = match config {
+        serde_yaml::Value::Mapping(mapping) => mapping,
+        serde_yaml::Value::Null => serde_yaml::Mapping::new(),
+        _ => anyhow::bail!("Negotiator config must be a mapping"),
+    };
+
+    let prefix = env_prefix(name);
+    for (var, value) in vars {
+        let field = match var.strip_prefix(&prefix) {
+            Some(field) if !field.is_empty() => field.to_lowercase(),
+            _ => continue,
+        };
+        let value = serde_yaml::from_str(&value)
+            .map_err(|e| anyhow::anyhow!("Invalid value of {}: {}", var, e))?;
+        log::debug!("Config field [{}] set from environment ({}).", field, var);
+        config.insert(serde_yaml::Value::String(field), value);
+    }
+    Ok(serde_yaml::Value::Mapping(config))
+}
+
+/// Creates `BlacklistReputation` configured in code instead of YAML.
+/// Unlike plugin constructor, it doesn't start logger.
+pub struct BlacklistReputationBuilder {
+    config: BlacklistReputationsConfig,
+    name: String,
+    store: Option<Arc<dyn ReputationStore>>,
+    handle: Option<Handle>,
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl Default for BlacklistReputationBuilder {
+    fn default() -> Self {
+        BlacklistReputationBuilder::from_config(BlacklistReputationsConfig::default())
+    }
+}
+
+impl BlacklistReputationBuilder {
+    pub fn new() -> BlacklistReputationBuilder {
+        BlacklistReputationBuilder::default()
+    }
+
+    pub fn from_config(config: BlacklistReputationsConfig) -> BlacklistReputationBuilder {
+        BlacklistReputationBuilder {
+            config,
+            name: String::new(),
+            store: None,
+            handle: None,
+            clock: None,
+        }
+    }
+
+    /// Name of plugin instance. Instances with different names keep separate state files.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn payment_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.config.payment_timeout = timeout;
+        self
+    }
+
+    pub fn strikes_before_blacklist(mut self, strikes: u32) -> Self {
+        self.config.strikes_before_blacklist = strikes;
+        self
+    }
+
+    pub fn blacklist_ttl(mut self, ttl: Option<std::time::Duration>) -> Self {
+        self.config.blacklist_ttl = ttl;
+        self
+    }
+
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.config.dry_run = dry_run;
+        self
+    }
+
+    pub fn persistence_format(mut self, format: Format) -> Self {
+        self.config.persistence_format = format;
+        self
+    }
+
+    pub fn reputation_penalty(mut self, penalty: f64) -> Self {
+        self.config.reputation_penalty = penalty;
+        self
+    }
+
+    /// Modifies remaining config fields, which don't have dedicated setter.
+    pub fn configure(mut self, f: impl FnOnce(&mut BlacklistReputationsConfig)) -> Self {
+        f(&mut self.config);
+        self
+    }
+
+    /// Keeps state in custom store instead of state files in working directory.
+    pub fn store(mut self, store: Arc<dyn ReputationStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Runtime for background tasks. Current Tokio runtime is used if not set.
+    pub fn handle(mut self, handle: Handle) -> Self {
+        self.handle = Some(handle);
+        self
+    }
+
+    /// Time source of payment deadlines and expiry. Tests use `TokioClock` with paused
+    /// Tokio time. `SystemClock` is used if not set.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    pub fn build(self, working_dir: PathBuf) -> anyhow::Result<BlacklistReputation> {
+        let config = self.config;
+        let handle = self.handle.or_else(|| Handle::try_current().ok());
+        // Timers restored from state are started during construction, so clock
+        // can't be replaced afterwards.
+        let clock = self.clock.unwrap_or_else(|| Arc::new(SystemClock));
+        let kill_switch = config
+            .kill_switch_path
+            .as_ref()
+            .map(|path| working_dir.join(path));
+
+        if config.enable_timeout_blacklist && config.payment_timeout < MIN_SAFE_PAYMENT_TIMEOUT {
+            log::warn!(
+                "Payment timeout {} is shorter than {}. Legitimate payments can take longer \
+                 to settle, so honest requestors may be blacklisted. Increase `payment_timeout` \
+                 unless short timeout is intended.",
+                humantime::format_duration(config.payment_timeout),
+                humantime::format_duration(MIN_SAFE_PAYMENT_TIMEOUT)
+            );
+        }
+
+        let mut reputation = match self.store {
+            Some(store) => BlacklistReputation::start(config, store, handle, clock)?,
+            None => {
+                let files = StateFiles::new(working_dir, config.persistence_format)
+                    .with_instance(&self.name)
+                    .with_path("blacklist", config.blacklist_path.as_deref())
+                    .with_path("agreements", config.agreements_path.as_deref());
+                let writable = match files.check_writable() {
+                    Ok(()) => true,
+                    Err(e) if config.fail_on_unwritable_workdir => return Err(e),
+                    Err(e) => {
+                        log::error!(
+                            "{}. STATE WON'T BE PERSISTED, blacklist will be lost on restart.",
+                            e
+                        );
+                        false
+                    }
+                };
+                // Nothing can be written to read-only directory, so there are
+                // no concurrent writes to prevent.
+                let lock = match StateLock::acquire(&files.lock_path()) {
+                    Ok(lock) => Some(lock),
+                    Err(e) if !writable => {
+                        log::warn!("Running without state lock: {}", e);
+                        None
+                    }
+                    Err(e) => return Err(e),
+                };
+                let store = Arc::new(
+                    FileStore::new(files, config.backend, config.on_parse_error)
+                        .with_fail_on_load_error(config.fail_on_load_error)
+                        .with_shared_blacklist(config.share_blacklist),
+                );
+                let mut reputation =
+                    BlacklistReputation::start(config, store.clone(), handle, clock)?;
+                reputation._lock = lock;
+
+                // With database backend there is no blacklist file to be edited.
+                if reputation.config.backend == Backend::File {
+                    reputation._watcher = watch_blacklist(store, reputation.state.clone())
+                        .map_err(|e| log::warn!("Blacklist file changes won't be reloaded: {}", e))
+                        .ok();
+                }
+                reputation
+            }
+        };
+
+        if let Some(path) = kill_switch {
+            reputation._kill_switch = watch_kill_switch(
+                path,
+                !reputation.config.enforcement_enabled,
+                reputation.state.clone(),
+            )
+            .map_err(|e| log::warn!("Kill switch won't work: {}", e))
+            .ok();
+        }
+        Ok(reputation)
+    }
+}
+
+impl BlacklistReputation {
+    /// Creates negotiator with state kept in custom `ReputationStore`.
+    /// Standard plugin constructor uses `FileStore`. Tasks are spawned on current
+    /// Tokio runtime if there is one.
+    pub fn with_store(
+        config: BlacklistReputationsConfig,
+        store: Arc<dyn ReputationStore>,
+    ) -> anyhow::Result<BlacklistReputation> {
+        BlacklistReputation::with_runtime(config, store, Handle::try_current().ok())
+    }
+
+    /// Creates negotiator spawning tasks on runtime given by `handle`.
+    /// Plugin creates it's own runtime, if `handle` is `None`.
+    pub fn with_runtime(
+        config: BlacklistReputationsConfig,
+        store: Arc<dyn ReputationStore>,
+        handle: Option<Handle>,
+    ) -> anyhow::Result<BlacklistReputation> {
+        BlacklistReputation::start(config, store, handle, Arc::new(SystemClock))
+    }
+
+    /// Payment timers of Agreements loaded from `store` are started with `clock`.
+    fn start(
+        config: BlacklistReputationsConfig,
+        store: Arc<dyn ReputationStore>,
+        handle: Option<Handle>,
+        clock: Arc<dyn Clock>,
+    ) -> anyhow::Result<BlacklistReputation> {
+        let (handle, runtime) = match handle {
+            Some(handle) => (handle, None),
+            None => {
+                let runtime = Runtime::new()?;
+                (runtime.handle().clone(), Some(runtime))
+            }
+        };
+        let mut tasks = Vec::new();
+
+        log::info!(
+            "Starting BlacklistReputation plugin ({} runtime).",
+            if runtime.is_some() { "own" } else { "host" }
+        );
+
+        let config = Arc::new(config);
+        let mut state = store.load()?;
+        // Older versions recorded every issuer of proposal.
+        state.stats.retain(|_, stats| stats.has_history());
+        state.enforcement_disabled = !config.enforcement_enabled;
+        if config.auto_timeout {
+            state.update_auto_timeout(&config);
+        }
+        let metrics = state.metrics.clone();
+        let state = Arc::new(Mutex::new(state));
+
+        if let Some(url) = &config.blacklist_source {
+            let mut remote = RemoteBlacklist::new(url.clone(), config.on_parse_error)?;
+            match store.load_remote_cache() {
+                Ok(Some(cache)) => match remote.restore_cache(cache) {
+                    Ok(blacklist) => {
+                        let (added, _) = lock(&state).merge_remote(blacklist);
+                        log::info!("Restored {} Nodes from cached remote blacklist.", added);
+                    }
+                    Err(e) => log::info!("Cached remote blacklist not used: {}", e),
+                },
+                Ok(None) => (),
+                Err(e) => log::warn!("Failed to load cached remote blacklist: {}", e),
+            }
+            // Blocking would panic on host runtime, so initial synchronization
+            // is done in background in that case.
+            let synchronized = match &runtime {
+                Some(runtime) => {
+                    runtime.block_on(sync_remote(&mut remote, &state, store.as_ref()));
+                    true
+                }
+                None => false,
+            };
+
+            let period = config.remote_refresh_interval;
+            let state_ = state.clone();
+            let store_ = store.clone();
+            tasks.push(handle.spawn(async move {
+                if !synchronized {
+                    sync_remote(&mut remote, &state_, store_.as_ref()).await;
+                }
+
+                let period = match period {
+                    Some(period) => period,
+                    None => return,
+                };
+                let mut interval = tokio::time::interval(period);
+                // First tick completes immediately and we have just synchronized.
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    sync_remote(&mut remote, &state_, store_.as_ref()).await;
+                }
+            }));
+        }
+
+        let recovery = config.reputation_recovery;
+        let period = config.reputation_recovery_interval;
+        let state_ = state.clone();
+        tasks.push(handle.spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                lock(&state_).recover_reputation(recovery);
+            }
+        }));
+
+        let writer = BlacklistWriter {
+            store: store.clone(),
+            state: state.clone(),
+            handle: handle.clone(),
+            debounce: config.blacklist_write_debounce,
+            pending: Arc::new(Mutex::new(None)),
+        };
+
+        if config.auto_timeout {
+            let config_ = config.clone();
+            let state_ = state.clone();
+            tasks.push(handle.spawn(async move {
+                let mut interval = tokio::time::interval(AUTO_TIMEOUT_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    lock(&state_).update_auto_timeout(&config_);
+                }
+            }));
+        }
+
+        if let Some(period) = config.sweep_interval() {
+            let config_ = config.clone();
+            let state_ = state.clone();
+            let writer_ = writer.clone();
+            let clock_ = clock.clone();
+            tasks.push(handle.spawn(async move {
+                let mut interval = tokio::time::interval(period);
+                loop {
+                    interval.tick().await;
+                    let mut state = lock(&state_);
+                    let now = clock_.now();
+                    let blacklisted = match config_.max_agreement_age {
+                        Some(max_age) => state.remove_stale_agreements(now, max_age, &config_),
+                        None => false,
+                    };
+                    let untracked = state.remove_untimed_agreements(now, &config_);
+
+                    let removed = state.remove_expired(now, &config_);
+                    if removed == 0 && untracked == 0 && !blacklisted {
+                        continue;
+                    }
+
+                    if removed > 0 {
+                        log::info!("Removed {} expired blacklist entries.", removed);
+                    }
+                    if let Err(e) = writer_.persist(&state) {
+                        log::warn!("Failed to persist blacklist: {}", e);
+                    }
+                }
+            }));
+        }
+
+        let period = config.flush_interval;
+        let state_ = state.clone();
+        let store_ = store.clone();
+        tasks.push(handle.spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = persist_with_history(store_.as_ref(), &mut lock(&state_)) {
+                    log::warn!("Failed to flush state: {}", e);
+                }
+            }
+        }));
+
+        let reputation = BlacklistReputation {
+            config,
+            state,
+            handle,
+            _runtime: runtime,
+            tasks,
+            _lock: None,
+            clock,
+            shut_down: false,
+            store,
+            writer,
+            _watcher: None,
+            _kill_switch: None,
+            metrics,
+        };
+
+        // Agreements terminated before restart still wait for payment.
+        reputation.resume_payment_timers();
+        Ok(reputation)
+    }
+
+    /// Starts timers of terminated Agreements loaded from persisted state or snapshot.
+    fn resume_payment_timers(&self) {
+        let pending = {
+            let state = self.locked_state();
+            state
+                .agreements
+                .values()
+                .filter_map(|record| {
+                    record
+                        .terminated
+                        .map(|terminated| (record.id.clone(), record.node, terminated))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for (agreement_id, node_id, terminated) in pending {
+            self.start_payment_timer(
+                agreement_id,
+                node_id,
+                terminated,
+                self.config.restart_grace_period,
+            );
+        }
+    }
+
+    /// Checks if Node is currently blacklisted. Expired entries are not taken into account.
+    pub fn is_blacklisted(&self, node: &NodeId) -> bool {
+        self.locked_state()
+            .find_blacklisted(node, self.clock.now(), &self.config)
+            .is_some()
+    }
+
+    /// Snapshot of all currently blacklisted Nodes.
+    pub fn blacklisted_nodes(&self) -> Vec<NodeId> {
+        let state = self.locked_state();
+        let now = self.clock.now();
+        let mut nodes = state
+            .blacklist
+            .iter()
+            .filter(|(_, entry)| !entry.is_expired_at(self.config.blacklist_ttl_for(entry), now))
+            .map(|(node, _)| *node)
+            .collect::<Vec<_>>();
+        nodes.extend(
+            state
+                .static_blacklist
+                .keys()
+                .filter(|node| !state.blacklist.contains_key(node)),
+        );
+        nodes.sort_by_key(|node| node.to_string());
+        nodes
+    }
+
+    /// Sends `BlacklistEvent` to `sender` each time Node is blacklisted.
+    pub fn with_events(self, sender: mpsc::Sender<BlacklistEvent>) -> Self {
+        self.locked_state().events = Some(sender);
+        self
+    }
+
+    /// Adds Node to blacklist on operator request. Change is persisted immediately.
+    pub fn blacklist_node(&mut self, node: NodeId, reason: String) {
+        log::info!("Manually blacklisting Node [{}]: {}", node, reason);
+
+        {
+            let mut state = self.locked_state();
+            let mut entry = BlacklistEntry::new(node, BlacklistReason::Manual(reason));
+            entry.added = self.clock.now();
+            state.remove_entry(&node);
+            state.add_entry(entry, &self.config);
+        }
+        self.persist_blacklist()
+            .map_err(|e| log::warn!("Failed to persist blacklist: {}", e))
+            .ok();
+    }
+
+    /// Removes Node from blacklist on operator request. Change is persisted immediately.
+    /// Returns false, if Node wasn't blacklisted.
+    /// Payment timers of Agreements already terminated by Node are cancelled, so they
+    /// can't blacklist it again.
+    pub fn unblacklist_node(&mut self, node: &NodeId) -> bool {
+        let (removed, forgiven) = {
+            let mut state = self.locked_state();
+            let removed = state.remove_entry(node).is_some();
+            let forgiven = if removed {
+                state.forgive_pending(node)
+            } else {
+                0
+            };
+            (removed, forgiven)
+        };
+        if removed {
+            log::info!("Manually removed Node [{}] from blacklist.", node);
+            let result = if forgiven > 0 {
+                // Forgiven Agreements must not be restored after restart.
+                self.store.persist(&self.locked_state())
+            } else {
+                self.persist_blacklist()
+            };
+            result
+                .map_err(|e| log::warn!("Failed to persist blacklist: {}", e))
+                .ok();
+        }
+        removed
+    }
+
+    /// Exports state, which should be carried over to another host.
+    pub fn export_snapshot(&self) -> ReputationSnapshot {
+        let state = self.locked_state();
+        let mut blacklist = state
+            .blacklist
+            .values()
+            .filter(|entry| !state.remote.contains(&entry.node))
+            .cloned()
+            .collect::<Vec<_>>();
+        blacklist.sort_by_key(|entry| entry.node.to_string());
+        let mut agreements = state.agreements.values().cloned().collect::<Vec<_>>();
+        agreements.sort_by_key(|record| record.signed);
+
+        ReputationSnapshot {
+            version: SNAPSHOT_VERSION,
+            created: self.clock.now(),
+            blacklist,
+            graylist: state.graylist.clone(),
+            stats: state.stats.clone(),
+            strikes: state.strikes.clone(),
+            paid_streak: state.paid_streak.clone(),
+            unpaid: state.unpaid.clone(),
+            notes: state.notes.clone(),
+            reputation: state.reputation.clone(),
+            abandon_streak: state.abandon_streak.clone(),
+            agreements,
+        }
+    }
+
+    /// Replaces state with snapshot exported by `export_snapshot`, possibly on another host.
+    /// Payment timers of terminated Agreements are restarted like after restart.
+    /// Change is persisted immediately.
+    pub fn import_snapshot(&mut self, snapshot: ReputationSnapshot) -> anyhow::Result<()> {
+        if snapshot.version > SNAPSHOT_VERSION {
+            anyhow::bail!(
+                "Snapshot version {} is not supported. Supported version: {}",
+                snapshot.version,
+                SNAPSHOT_VERSION
+            );
+        }
+        log::info!(
+            "Importing snapshot created at {}: {} blacklisted Nodes, {} tracked agreements.",
+            snapshot.created.to_rfc3339(),
+            snapshot.blacklist.len(),
+            snapshot.agreements.len()
+        );
+
+        {
+            let mut state = self.locked_state();
+            let tracked = state.agreements.keys().cloned().collect::<Vec<_>>();
+            for agreement_id in tracked {
+                if let Some(mut record) = state.untrack(&agreement_id) {
+                    record.stop_timer();
+                }
+            }
+            for record in snapshot.agreements {
+                state.track(record);
+            }
+
+            // Remote blacklist is kept, it is fetched from it's source anyway.
+            let local = state
+                .blacklist
+                .keys()
+                .filter(|node| !state.remote.contains(node))
+                .cloned()
+                .collect::<Vec<_>>();
+            for node in local {
+                state.remove_entry(&node);
+            }
+            state.insert_entries(snapshot.blacklist);
+            state.graylist = snapshot.graylist;
+            state.stats = snapshot.stats;
+            state.strikes = snapshot.strikes;
+            state.paid_streak = snapshot.paid_streak;
+            state.unpaid = snapshot.unpaid;
+            state.notes = snapshot.notes;
+            state.reputation = snapshot.reputation;
+            state.abandon_streak = snapshot.abandon_streak;
+            state.late.clear();
+        }
+
+        self.resume_payment_timers();
+        self.store.persist(&self.locked_state())
+    }
+
+    /// Clears payment history, strikes and unpaid amounts of all Nodes, e.g. after incident
+    /// corrupting them. Unlike removing Nodes from blacklist, active bans and graylist
+    /// are kept. Change is persisted immediately.
+    pub fn reset_stats(&mut self) {
+        let mut state = self.locked_state();
+        log::info!("Resetting statistics of {} Nodes.", state.stats.len());
+        state.stats.clear();
+        state.strikes.clear();
+        state.paid_streak.clear();
+        state.unpaid.clear();
+        state.abandon_streak.clear();
+        state.late.clear();
+
+        self.store
+            .persist(&state)
+            .map_err(|e| log::warn!("Failed to persist state: {}", e))
+            .ok();
+    }
+
+    /// Attaches operator note to Node or removes it, if `note` is None. Change is
+    /// persisted immediately.
+    pub fn set_note(&mut self, node: NodeId, note: Option<String>) {
+        {
+            let mut state = self.locked_state();
+            match note {
+                Some(note) => state.notes.insert(node, note),
+                None => state.notes.remove(&node),
+            };
+        }
+        self.persist_blacklist()
+            .map_err(|e| log::warn!("Failed to persist notes: {}", e))
+            .ok();
+    }
+
+    /// Operator note attached to Node.
+    pub fn note(&self, node: &NodeId) -> Option<String> {
+        self.locked_state().notes.get(node).cloned()
+    }
+
+    /// Imports blacklist distributed at runtime, e.g. by fleet manager. Change is persisted
+    /// immediately.
+    pub fn merge_blacklist(&mut self, entries: Vec<BlacklistEntry>, mode: MergeMode) {
+        let (added, removed) = self.locked_state().merge_entries(entries, mode);
+        log::info!(
+            "Imported blacklist ({:?}): {} Nodes added, {} removed.",
+            mode,
+            added,
+            removed
+        );
+
+        self.persist_blacklist()
+            .map_err(|e| log::warn!("Failed to persist blacklist: {}", e))
+            .ok();
+    }
+
+    /// Reports payment for Agreement together with paid amount. Should be used instead of
+    /// `AgreementEvent::InvoicePaid` by applications, which know the amount, so partial
+    /// payments can be distinguished from full payments.
+    pub fn report_payment(&mut self, agreement_id: &str, amount_paid: f64) {
+        self.payment_received(agreement_id, Some(amount_paid));
+    }
+
+    /// Stops all tasks and timers and flushes state. Should be called by provider agent
+    /// on controlled shutdown, `Drop` is only best-effort fallback. Tracked Agreements are
+    /// persisted, so their timers will be restarted on next startup.
+    pub async fn shutdown(&mut self) -> anyhow::Result<()> {
+        if self.shut_down {
+            return Ok(());
+        }
+        log::info!("Shutting down BlacklistReputation plugin.");
+        self.writer.cancel();
+
+        let mut tasks = self.tasks.drain(..).collect::<Vec<_>>();
+        tasks.extend(
+            self.locked_state()
+                .agreements
+                .values_mut()
+                .filter_map(|record| record.timer.take()),
+        );
+        for task in &tasks {
+            task.abort();
+        }
+        for task in tasks {
+            task.await.ok();
+        }
+
+        persist_with_history(self.store.as_ref(), &mut self.locked_state())?;
+        self.report_expiring();
+        self.shut_down = true;
+        Ok(())
+    }
+
+    /// Logs Nodes, which would be removed from blacklist within `shutdown_report_window`.
+    fn report_expiring(&self) {
+        if !self.config.shutdown_report {
+            return;
+        }
+
+        let now = self.clock.now();
+        let until = chrono::Duration::from_std(self.config.shutdown_report_window)
+            .map(|window| now + window)
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        let state = self.locked_state();
+        let expiring = state.expiring(until, &self.config);
+
+        log::info!(
+            "Shutdown report: {} of {} blacklisted Nodes would be unblacklisted within {}.",
+            expiring.len(),
+            state.blacklist.len(),
+            humantime::format_duration(self.config.shutdown_report_window)
+        );
+        for (entry, expires) in expiring {
+            log::info!(
+                event = "would_unblacklist",
+                node:% = entry.node,
+                expires:% = expires.to_rfc3339(),
+                reason:% = entry.reason;
+                "Node [{}] would be unblacklisted at {} (in {}). Blacklisted at {}. Reason: {}",
+                entry.node,
+                expires.to_rfc3339(),
+                humantime::format_duration(
+                    (expires - now).to_std().unwrap_or_default()
+                ),
+                entry.added.to_rfc3339(),
+                entry.reason
+            );
+        }
+    }
+
+    /// Reputation of Node in range [0.0, 1.0]:
+    /// - 1.0 for whitelisted Nodes,
+    /// - 0.0 for Nodes on blacklist (including static blacklist), even if blacklisted
+    ///   Nodes are allowed on probation or with `score_penalty`,
+    /// - otherwise product of reputation factor (`reputation_penalty` or `decay`),
+    ///   strike factor `1 - strikes / strikes_before_blacklist` and `GRAYLIST_FACTOR`,
+    ///   if Node is graylisted.
+    ///
+    /// Node without payment history has reputation 1.0.
+    pub fn reputation_score(&self, node: &NodeId) -> f64 {
+        let mut state = self.locked_state();
+        if state.whitelist.contains(node) {
+            return 1.0;
+        }
+        let now = self.clock.now();
+        if state.find_blacklisted(node, now, &self.config).is_some() {
+            return 0.0;
+        }
+
+        let factor = state.reputation_factor(node, now, &self.config);
+        let required = self.config.strikes_before_blacklist.max(1);
+        let strikes = state.strikes.get(node).cloned().unwrap_or(0);
+        let strike_factor = 1.0 - (strikes as f64 / required as f64).min(1.0);
+        let graylist_factor = if state.is_graylisted(node, now, &self.config) {
+            GRAYLIST_FACTOR
+        } else {
+            1.0
+        };
+        (factor * strike_factor * graylist_factor).clamp(0.0, 1.0)
+    }
+
+    /// Payment history and activity of Node.
+    pub fn node_stats(&self, node: &NodeId) -> Option<NodeStats> {
+        self.locked_state().stats.get(node).cloned()
+    }
+
+    /// Summary of plugin state for monitoring.
+    pub fn stats(&self) -> ReputationStats {
+        let state = self.locked_state();
+        ReputationStats {
+            blacklisted: state.blacklist.len(),
+            tracked_agreements: state.agreements.len(),
+            max_tracked_agreements: self.config.max_tracked_agreements,
+            last_remote_sync: state.last_remote_sync,
+        }
+    }
+
+    /// Number of tracked Agreements, including ones not terminated yet.
+    pub fn tracked_count(&self) -> usize {
+        self.locked_state().agreements.len()
+    }
+
+    /// The earliest payment deadline of terminated Agreements waiting for payment.
+    /// `None`, if payment timeout is disabled with `enable_timeout_blacklist`.
+    pub fn next_deadline(&self) -> Option<DateTime<Utc>> {
+        if !self.config.enable_timeout_blacklist {
+            return None;
+        }
+        let state = self.locked_state();
+        state
+            .agreements
+            .values()
+            .filter_map(|record| self.config.payment_deadline(record, state.auto_timeout))
+            .min()
+    }
+
+    /// Dumps internal state for debugging. Safe to call concurrently with negotiator
+    /// handlers, since it works on a snapshot taken under state lock.
+    pub fn dump_state(&self) -> serde_json::Value {
+        let state = self.locked_state();
+
+        let mut blacklist = state.blacklist.values().collect::<Vec<_>>();
+        blacklist.sort_by_key(|entry| entry.node.to_string());
+        let blacklist = blacklist
+            .into_iter()
+            .map(|entry| {
+                serde_json::json!({
+                    "node": entry.node,
+                    "reason": entry.reason.to_string(),
+                    "added": entry.added,
+                    "agreement_id": entry.agreement_id,
+                    "expires": entry.expires(self.config.blacklist_ttl_for(entry)),
+                    "policy": entry.policy,
+                    "endpoint": entry.endpoint,
+                    "remote": state.remote.contains(&entry.node),
+                    "note": state.notes.get(&entry.node),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut agreements = state.agreements.values().collect::<Vec<_>>();
+        agreements.sort_by_key(|record| record.signed);
+        let agreements = agreements
+            .into_iter()
+            .map(|record| {
+                let deadline = self.config.payment_deadline(record, state.auto_timeout);
+                serde_json::json!({
+                    "id": record.id,
+                    "node": record.node,
+                    "signed": record.signed,
+                    "terminated": record.terminated,
+                    "payment_deadline": deadline,
+                    "amount_due": record.amount_due(),
+                    "timer_active": record.timer_active(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let active_timers = state
+            .agreements
+            .values()
+            .filter(|record| record.timer_active())
+            .count();
+
+        serde_json::json!({
+            "blacklist": blacklist,
+            "agreements": agreements,
+            "active_timers": active_timers,
+            "stats": state.stats,
+            "notes": state.notes,
+        })
+    }
+
+    /// Registry with plugin metrics. See `metrics` module for scraping instructions.
+    pub fn metrics_registry(&self) -> &prometheus::Registry {
+        self.metrics.registry()
+    }
+
+    fn persist_blacklist(&self) -> anyhow::Result<()> {
+        self.writer.persist(&self.locked_state())
+    }
+
+    fn locked_state(&self) -> MutexGuard<'_, BlacklistState> {
+        lock(&self.state)
+    }
+
+    /// Entry added to shared `ReputationStore` by other component. Expired entries
+    /// and store failures are treated as Node not being blacklisted.
+    fn shared_entry(&self, node: &NodeId) -> Option<BlacklistEntry> {
+        match self.store.find_blacklisted(node) {
+            Ok(Some(entry))
+                if !entry
+                    .is_expired_at(self.config.blacklist_ttl_for(&entry), self.clock.now()) =>
+            {
+                Some(entry)
+            }
+            Ok(_) => None,
+            Err(e) => {
+                log::warn!("Failed to check Node [{}] in shared store: {}", node, e);
+                None
+            }
+        }
+    }
+
+    /// Adds `golem.reputation.*` properties describing enforced payment policy to the offer.
+    /// Properties already present in the offer are left untouched, so payment timeout
+    /// set by other component becomes timeout of the Agreement.
+    fn advertise_policy(&self, demand: &ProposalView, offer: &mut ProposalView) {
+        let policy = self.config.select_policy(&demand.content.properties);
+        let auto = self.locked_state().auto_timeout;
+        let timeout =
+            self.config
+                .effective_payment_timeout(&demand.issuer, policy.as_deref(), auto);
+        let policy = [
+            ("payment_timeout", serde_json::json!(timeout.as_secs())),
+            (
+                "strikes_before_blacklist",
+                serde_json::json!(self.config.strikes_before_blacklist_for(policy.as_deref())),
+            ),
+            ("blacklisting", serde_json::json!(!self.config.dry_run)),
+        ];
+
+        for (name, value) in policy {
+            insert_property(
+                &mut offer.content.properties,
+                &["golem", "reputation", name],
+                value,
+            );
+        }
+    }
+
+    /// Stops tracking paid Agreement. Payments lower than amount due (minus
+    /// `allow_partial_payment_pct`) are counted as strikes.
+    fn payment_received(&self, agreement_id: &str, amount_paid: Option<f64>) {
+        let mut state = self.locked_state();
+        let mut record = match state.untrack(agreement_id) {
+            Some(record) => record,
+            None => {
+                let changed = state.undo_late_strike(agreement_id, self.clock.now(), &self.config);
+                drop(state);
+                if changed {
+                    self.persist_blacklist()
+                        .map_err(|e| log::warn!("Failed to persist blacklist: {}", e))
+                        .ok();
+                }
+                return;
+            }
+        };
+        record.stop_timer();
+        let now = self.clock.now();
+        state.seen(record.node, now);
+        if let Some(terminated) = record.terminated {
+            state.record_latency(record.node, terminated, now);
+        }
+
+        let amount_due = record.amount_due();
+        record.amount_paid = amount_paid;
+        record.amount_due = amount_due;
+        state.record_outcome(&record, Some(now), None, &self.config);
+
+        let changed = match (amount_paid, amount_due) {
+            (Some(paid), Some(due))
+                if paid < due * (1.0 - self.config.allow_partial_payment_pct / 100.0) =>
+            {
+                state.metrics.partial_payments.inc();
+                let decision_id = Uuid::new_v4().to_string();
+                log::info!(
+                    event = "partial_payment",
+                    decision_id = decision_id.as_str(),
+                    node:% = record.node,
+                    agreement_id = agreement_id;
+                    "Node [{}] paid partially for agreement [{}]. Paid: {}, due: {}.",
+                    record.node,
+                    agreement_id,
+                    paid,
+                    due
+                );
+                state.add_strike(
+                    &record,
+                    BlacklistReason::PartialPayment,
+                    &decision_id,
+                    self.clock.now(),
+                    &self.config,
+                )
+            }
+            _ => {
+                state.metrics.invoices_paid.inc();
+                log::info!(
+                    "Node [{}] paid invoice for agreement [{}] (paid: {:?}, due: {:?}). Stop tracking..",
+                    record.node,
+                    agreement_id,
+                    amount_paid,
+                    amount_due
+                );
+                state.add_payment(record.node, self.clock.now(), &self.config)
+            }
+        };
+
+        drop(state);
+        if changed {
+            self.persist_blacklist()
+                .map_err(|e| log::warn!("Failed to persist blacklist: {}", e))
+                .ok();
+        }
+    }
+
+    /// Spawns task checking if Agreement was paid in `payment_timeout` after termination.
+    /// Task handle is stored in `TrackedAgreement`, so it can be cancelled.
+    /// If deadline already passed, Node gets `grace` period to pay before checking.
+    /// Deadline is compared with `Clock`, so it isn't postponed by system suspend.
+    fn start_payment_timer(
+        &self,
+        agreement_id: String,
+        node_id: NodeId,
+        terminated: DateTime<Utc>,
+        grace: Option<std::time::Duration>,
+    ) {
+        if !self.config.enable_timeout_blacklist {
+            log::debug!(
+                "Payment timeout disabled. Not waiting for payment for agreement [{}].",
+                agreement_id
+            );
+            return;
+        }
+
+        let state = self.state.clone();
+        let config = self.config.clone();
+        let writer = self.writer.clone();
+
+        let (record, auto) = {
+            let state = lock(&self.state);
+            (
+                state.agreements.get(&agreement_id).cloned(),
+                state.auto_timeout,
+            )
+        };
+        let record = record
+            .unwrap_or_else(|| TrackedAgreement::new(agreement_id.clone(), node_id, terminated));
+        let timeout = self.config.payment_timeout_of(&record, auto);
+        let remaining = chrono::Duration::from_std(timeout)
+            .map(|timeout| terminated + timeout - self.clock.now())
+            .ok()
+            .and_then(|remaining| remaining.to_std().ok())
+            .unwrap_or_default();
+        let remaining = match grace {
+            Some(grace) if remaining.is_zero() => {
+                log::info!(
+                    "Payment deadline for agreement [{}] passed during restart. Granting {} grace period.",
+                    agreement_id,
+                    humantime::format_duration(grace)
+                );
+                grace
+            }
+            _ => remaining,
+        };
+
+        let source = if record.payment_timeout.is_some() {
+            "agreed".to_string()
+        } else if self.config.payment_timeout_overrides.contains_key(&node_id) {
+            "node override".to_string()
+        } else if auto.is_some()
+            && !self
+                .config
+                .has_configured_timeout(&node_id, record.policy.as_deref())
+        {
+            "auto".to_string()
+        } else if let Some(policy) = &record.policy {
+            format!("policy {}", policy)
+        } else {
+            "global".to_string()
+        };
+
+        log::debug!(
+            "Setting timer ({}, {} payment timeout: {}) for agreement [{}], node: {}",
+            humantime::format_duration(remaining),
+            source,
+            humantime::format_duration(timeout),
+            agreement_id,
+            node_id
+        );
+
+        let agreement_id_ = agreement_id.clone();
+        let clock = self.clock.clone();
+        let deadline = chrono::Duration::from_std(remaining)
+            .map(|remaining| clock.now() + remaining)
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        let timer = self.handle.spawn(async move {
+            let agreement_id = agreement_id_;
+            loop {
+                let remaining = (deadline - clock.now()).to_std().unwrap_or_default();
+                if remaining.is_zero() {
+                    break;
+                }
+                tokio::time::sleep(remaining.min(DEADLINE_CHECK_INTERVAL)).await;
+            }
+
+            let mut state = lock(&state);
+
+            // If we don't find Agreement in the map, it have been paid.
+            let blacklisted = match state.untrack(&agreement_id) {
+                Some(record) => {
+                    let decision_id = Uuid::new_v4().to_string();
+                    log::info!(
+                        event = "payment_timeout",
+                        decision_id = decision_id.as_str(),
+                        node:% = node_id,
+                        agreement_id = agreement_id.as_str();
+                        "Node [{}] didn't pay agreement [{}].",
+                        node_id,
+                        agreement_id
+                    );
+                    state.metrics.payment_timeouts.inc();
+                    state.record_outcome(&record, None, None, &config);
+                    let strike = state.count_strike(
+                        &record,
+                        BlacklistReason::PaymentTimeout,
+                        &decision_id,
+                        clock.now(),
+                        &config,
+                    );
+
+                    if let Some(grace) = config
+                        .payment_grace
+                        .and_then(|grace| chrono::Duration::from_std(grace).ok())
+                    {
+                        let now = clock.now();
+                        state.add_late(&record, strike, now, now + grace);
+                    }
+                    strike.blacklisted
+                }
+                None => false,
+            };
+
+            if blacklisted {
+                writer
+                    .persist(&state)
+                    .map_err(|e| log::warn!("Failed to persist blacklist: {}", e))
+                    .ok();
+            }
+        });
+
+        if let Some(record) = self.locked_state().agreements.get_mut(&agreement_id) {
+            record.stop_timer();
+            record.timer = Some(timer);
+        }
+    }
+}
+
+impl Drop for BlacklistReputation {
+    /// Tasks must be aborted explicitly, because host runtime outlives negotiator.
+    fn drop(&mut self) {
+        if self.shut_down {
+            return;
+        }
+
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+        self.writer.cancel();
+
+        let mut state = self.locked_state();
+        for record in state.agreements.values_mut() {
+            record.stop_timer();
+        }
+        if let Err(e) = persist_with_history(self.store.as_ref(), &mut state) {
+            log::error!("Failed to persist state on drop. State is lost: {}", e);
+        }
+        drop(state);
+        self.report_expiring();
+    }
+}
+
+/// Reputation tracking shouldn't block negotiations and Agreements, so handlers never
+/// return errors. Malformed proposals, Agreements and events are logged and ignored.
+impl NegotiatorComponent for BlacklistReputation {
+    /// BlacklistReputation will reject any Node on blacklist, unless it is whitelisted.
+    /// Score of other Nodes is lowered according to their reputation.
+    fn negotiate_step(
+        &mut self,
+        demand: &ProposalView,
+        mut offer: ProposalView,
+        mut score: Score,
+    ) -> anyhow::Result<NegotiationResult> {
+        if self.config.advertise_policy {
+            self.advertise_policy(demand, &mut offer);
+        }
+
+        let enforced = !self.locked_state().enforcement_disabled;
+        let mut state = self.locked_state();
+        state.seen_proposal(demand.issuer, self.clock.now());
+        if state.whitelist.contains(&demand.issuer) {
+            log::debug!("Node {} allowed (whitelisted).", demand.issuer);
+            return Ok(NegotiationResult::Ready {
+                proposal: offer,
+                score,
+            });
+        }
+
+        if enforced && !self.config.allowed_platforms.is_empty() {
+            let platforms = payment_platforms(&demand.content.properties);
+            let allowed = platforms
+                .iter()
+                .any(|platform| self.config.allowed_platforms.contains(platform));
+            if !platforms.is_empty() && !allowed {
+                let reason = format!(
+                    "Payment platforms [{}] not accepted. Accepted platforms: [{}].",
+                    platforms.join(", "),
+                    self.config.allowed_platforms.join(", ")
+                );
+
+                if self.config.dry_run {
+                    log::info!("[Dry run] Would reject node {}: {}", demand.issuer, reason);
+                } else {
+                    log::info!(
+                        event = "rejected",
+                        node:% = demand.issuer,
+                        reason = reason.as_str();
+                        "Rejecting node {}: {}",
+                        demand.issuer,
+                        reason
+                    );
+                    return Ok(NegotiationResult::Reject {
+                        reason: RejectReason::new(reason),
+                        is_final: true,
+                    });
+                }
+            }
+        }
+
+        let is_static = state.static_blacklist.contains_key(&demand.issuer);
+        let note = state.notes.get(&demand.issuer).cloned();
+        if let Some(entry) = state.find_blacklisted(&demand.issuer, self.clock.now(), &self.config)
+        {
+            if !enforced {
+                log::info!(
+                    "Enforcement disabled. Allowing blacklisted node: {}",
+                    demand.issuer
+                );
+                return Ok(NegotiationResult::Ready {
+                    proposal: offer,
+                    score,
+                });
+            }
+
+            if self.config.redemption_payments > 0
+                && !self.config.dry_run
+                && !is_static
+                && !entry.is_manual()
+            {
+                let factor =
+                    state.reputation_factor(&demand.issuer, self.clock.now(), &self.config);
+                score.value *= factor;
+
+                log::info!(
+                    "Blacklisted node {} allowed on probation. Reputation factor: {:.3}",
+                    demand.issuer,
+                    factor
+                );
+                return Ok(NegotiationResult::Ready {
+                    proposal: offer,
+                    score,
+                });
+            }
+
+            if self.config.dry_run {
+                log::info!("[Dry run] Would reject blacklisted node: {}", demand.issuer);
+                return Ok(NegotiationResult::Ready {
+                    proposal: offer,
+                    score,
+                });
+            }
+
+            if self.config.blacklist_action == BlacklistAction::ScorePenalty && !is_static {
+                log::info!(
+                    "Blacklisted node {} allowed as last resort. Lowering score by {}. Reason: {}",
+                    demand.issuer,
+                    self.config.blacklist_score_penalty,
+                    entry.reason
+                );
+                score.value -= self.config.blacklist_score_penalty;
+                return Ok(NegotiationResult::Ready {
+                    proposal: offer,
+                    score,
+                });
+            }
+
+            let expires = if is_static {
+                None
+            } else {
+                entry.expires(self.config.blacklist_ttl_for(entry))
+            };
+            log::info!(
+                event = "rejected",
+                node:% = demand.issuer,
+                reason:% = entry.reason,
+                note = note.as_deref();
+                "Rejecting blacklisted node: {}. Reason: {}{}",
+                demand.issuer,
+                entry.reason,
+                note.as_ref()
+                    .map(|note| format!(". Note: {}", note))
+                    .unwrap_or_default()
+            );
+
+            return Ok(NegotiationResult::Reject {
+                reason: RejectReason::new(self.config.reject_message(entry, expires)),
+                // Requestor can retry after temporary ban expires.
+                is_final: expires.is_none(),
+            });
+        }
+
+        if enforced {
+            if let Some(entry) = self.shared_entry(&demand.issuer) {
+                if self.config.dry_run {
+                    log::info!(
+                        "[Dry run] Would reject node {} blacklisted in shared store.",
+                        demand.issuer
+                    );
+                } else {
+                    log::info!(
+                        event = "rejected",
+                        node:% = demand.issuer,
+                        reason:% = entry.reason,
+                        shared = true;
+                        "Rejecting node {} blacklisted in shared store. Reason: {}",
+                        demand.issuer,
+                        entry.reason
+                    );
+                    let expires = entry.expires(self.config.blacklist_ttl_for(&entry));
+                    return Ok(NegotiationResult::Reject {
+                        reason: RejectReason::new(self.config.reject_message(&entry, expires)),
+                        is_final: expires.is_none(),
+                    });
+                }
+            }
+        }
+
+        let factor = state.reputation_factor(&demand.issuer, self.clock.now(), &self.config);
+        if !self.config.dry_run {
+            score.value *= factor;
+        }
+
+        if state.is_graylisted(&demand.issuer, self.clock.now(), &self.config) {
+            log::info!(
+                "Node {} is graylisted. Lowering score by {}.",
+                demand.issuer,
+                self.config.graylist_penalty
+            );
+            if !self.config.dry_run {
+                score.value -= self.config.graylist_penalty;
+            }
+        }
+
+        let new_node = state
+            .stats
+            .get(&demand.issuer)
+            .map(|stats| stats.total == 0)
+            .unwrap_or(true);
+        if new_node && self.config.new_node_score_penalty > 0.0 {
+            log::debug!(
+                "Node {} has no Agreements yet. Lowering score by {}.",
+                demand.issuer,
+                self.config.new_node_score_penalty
+            );
+            if !self.config.dry_run {
+                score.value -= self.config.new_node_score_penalty;
+            }
+        }
+
+        if self.config.abandon_threshold > 0 {
+            let now = self.clock.now();
+            state.track_proposal(&demand.id, demand.issuer, now);
+            state.track_proposal(&offer.id, demand.issuer, now);
+        }
+
+        log::debug!(
+            "Node {} allowed (not blacklisted). Reputation factor: {:.3}",
+            demand.issuer,
+            factor
+        );
+        Ok(NegotiationResult::Ready {
+            proposal: offer,
+            score,
+        })
+    }
+
+    /// Negotiator will expect Invoice to be paid in specified deadline after termination.
+    /// We must store timestamp
+    fn on_agreement_terminated(
+        &mut self,
+        agreement_id: &str,
+        _result: &AgreementResult,
+    ) -> anyhow::Result<()> {
+        log::trace!("on_agreement_terminated [{}]", agreement_id);
+
+        let now = self.clock.now();
+        let node_id = {
+            let mut state = self.locked_state();
+            let node = match state.agreements.get_mut(agreement_id) {
+                Some(record) if record.terminated.is_some() => {
+                    log::debug!(
+                        "Agreement [{}] already terminated. Keeping payment timer.",
+                        agreement_id
+                    );
+                    return Ok(());
+                }
+                Some(record) => {
+                    record.terminated = Some(now);
+                    record.node
+                }
+                None => return Ok(()),
+            };
+            state.store_agreement(agreement_id);
+            state.seen(node, now);
+            node
+        };
+
+        self.start_payment_timer(agreement_id.to_string(), node_id, now, None);
+        Ok(())
+    }
+
+    /// Store `Agreement` information and track it's state.
+    fn on_agreement_approved(&mut self, agreement: &AgreementView) -> anyhow::Result<()> {
+        log::trace!("on_agreement_approved [{}]", agreement.id);
+
+        if self
+            .config
+            .is_ignored(&agreement.id, agreement.pointer("/demand/properties"))
+        {
+            log::debug!(
+                "Agreement [{}] excluded from tracking by config.",
+                agreement.id
+            );
+            return Ok(());
+        }
+
+        let node = match agreement.requestor_id() {
+            Ok(node) => node,
+            Err(e) => {
+                log::warn!(
+                    "Can't get requestor of agreement [{}]: {}. Agreement won't be tracked.",
+                    agreement.id,
+                    e
+                );
+                return Ok(());
+            }
+        };
+
+        let signed = match agreement.pointer_typed::<DateTime<Utc>>("/approved_date") {
+            Ok(signed) => signed,
+            Err(e) => match self.config.missing_approved_date {
+                MissingApprovedDate::Now => {
+                    log::warn!(
+                        "Agreement [{}] has no valid approved date: {}. Using current time.",
+                        agreement.id,
+                        e
+                    );
+                    self.clock.now()
+                }
+                MissingApprovedDate::Skip => {
+                    log::warn!(
+                        "Agreement [{}] has no valid approved date: {}. Agreement won't be tracked.",
+                        agreement.id,
+                        e
+                    );
+                    return Ok(());
+                }
+            },
+        };
+        let signed = clamp_signed(&agreement.id, signed, self.clock.now(), &self.config);
+
+        if !self.config.allowed_platforms.is_empty() {
+            if let Some(platform) = agreement
+                .pointer("/demand/properties/golem/com/payment/chosen-platform")
+                .and_then(|platform| platform.as_str())
+            {
+                if !self.config.allowed_platforms.iter().any(|p| p == platform) {
+                    log::warn!(
+                        "Agreement [{}] uses payment platform [{}], which isn't accepted.",
+                        agreement.id,
+                        platform
+                    );
+                }
+            }
+        }
+
+        let mut record = TrackedAgreement::new(agreement.id.clone(), node, signed);
+        record.pricing = LinearPricing::from_agreement(agreement);
+        record.policy = agreement
+            .pointer("/demand/properties")
+            .and_then(|properties| self.config.select_policy(properties));
+        record.endpoint = agreement
+            .pointer("/demand/properties")
+            .and_then(|properties| self.config.endpoint(properties));
+        record.payment_timeout = agreement
+            .pointer("/offer/properties/golem/reputation/payment_timeout")
+            .and_then(|timeout| timeout.as_u64())
+            .map(std::time::Duration::from_secs);
+        if let Some(policy) = &record.policy {
+            log::info!(
+                "Applying policy [{}] to agreement [{}].",
+                policy,
+                agreement.id
+            );
+        }
+
+        {
+            let mut state = self.locked_state();
+            if state.agreements.contains_key(&agreement.id) {
+                log::warn!(
+                    "Agreement [{}] approved again. Keeping already tracked agreement.",
+                    agreement.id
+                );
+                return Ok(());
+            }
+
+            if let Some(limit) = self.config.max_tracked_agreements {
+                if state.agreements.len() >= limit {
+                    match self.config.tracking_limit_action {
+                        TrackingLimitAction::Refuse => {
+                            log::warn!(
+                                "Tracking limit of {} agreements reached. Agreement [{}] with node [{}] won't be tracked.",
+                                limit,
+                                agreement.id,
+                                node
+                            );
+                            return Ok(());
+                        }
+                        TrackingLimitAction::Evict => {
+                            let oldest = state
+                                .agreements
+                                .values()
+                                .min_by_key(|record| record.signed)
+                                .map(|record| record.id.clone());
+                            if let Some(mut evicted) =
+                                oldest.and_then(|oldest| state.untrack(&oldest))
+                            {
+                                evicted.stop_timer();
+                                log::warn!(
+                                    "Tracking limit of {} agreements reached. Not tracking the oldest agreement [{}] with node [{}] anymore.",
+                                    limit,
+                                    evicted.id,
+                                    evicted.node
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+
+            state.track(record);
+            state.abandon_streak.remove(&node);
+            state.proposals.retain(|_, (other, _)| *other != node);
+            state.stats.entry(node).or_default().total += 1;
+            state.seen(node, self.clock.now());
+            state.metrics.agreements_tracked.inc();
+
+            log::info!(
+                "Registered agreement [{}] for node [{}].",
+                agreement.id,
+                node
+            );
+            Ok(())
+        }
+    }
+
+    /// Requestor rejected proposal instead of signing Agreement.
+    fn on_proposal_rejected(&mut self, proposal_id: &str) -> anyhow::Result<()> {
+        log::trace!("on_proposal_rejected [{}]", proposal_id);
+
+        let blacklisted =
+            self.locked_state()
+                .proposal_abandoned(proposal_id, self.clock.now(), &self.config);
+        if blacklisted {
+            self.persist_blacklist()
+                .map_err(|e| log::warn!("Failed to persist blacklist: {}", e))
+                .ok();
+        }
+        Ok(())
+    }
+
+    /// Notifies `NegotiatorComponent`, about events related to Agreement appearing after
+    /// it's termination.
+    fn on_agreement_event(
+        &mut self,
+        agreement_id: &str,
+        event: &AgreementEvent,
+    ) -> anyhow::Result<()> {
+        log::trace!("on_agreement_event [{}]", agreement_id);
+
+        let mut state = self.locked_state();
+        match event {
+            AgreementEvent::InvoicePaid => {
+                drop(state);
+                // Event doesn't carry paid amount, so we assume full payment.
+                self.payment_received(agreement_id, None);
+                Ok(())
+            }
+            AgreementEvent::InvoiceRejected => {
+                if let Some(mut record) = state.untrack(agreement_id) {
+                    record.stop_timer();
+                    state.metrics.invoices_rejected.inc();
+                    let decision_id = Uuid::new_v4().to_string();
+                    log::info!(
+                        event = "invoice_rejected",
+                        decision_id = decision_id.as_str(),
+                        node:% = record.node,
+                        agreement_id = agreement_id;
+                        "Node [{}] rejected invoice for agreement [{}].",
+                        record.node,
+                        agreement_id
+                    );
+                    let now = self.clock.now();
+                    state.record_outcome(&record, None, Some(now), &self.config);
+                    let blacklisted = state.add_strike(
+                        &record,
+                        BlacklistReason::InvoiceRejected,
+                        &decision_id,
+                        now,
+                        &self.config,
+                    );
+
+                    drop(state);
+                    if blacklisted {
+                        self.persist_blacklist()
+                            .map_err(|e| log::warn!("Failed to persist blacklist: {}", e))
+                            .ok();
+                    }
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+register_negotiators!(BlacklistReputation);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TokioClock;
+    use crate::storage::MemoryStore;
+    use std::time::Duration;
+    use ya_negotiator_shared_lib_interface::ya_negotiator_component::OfferTemplate;
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn node(n: u32) -> NodeId {
+        parse_node_id(&format!("0x{:040x}", n)).unwrap()
+    }
+
+    fn proposal(issuer: NodeId) -> ProposalView {
+        ProposalView {
+            content: OfferTemplate {
+                properties: serde_json::json!({}),
+                constraints: String::new(),
+            },
+            id: format!("proposal-{}", issuer),
+            issuer,
+            timestamp: Utc::now(),
+        }
+    }
+
+    fn agreement(id: &str, requestor: NodeId, approved: DateTime<Utc>) -> AgreementView {
+        AgreementView {
+            id: id.to_string(),
+            json: serde_json::json!({
+                "agreementId": id,
+                "approved_date": approved.to_rfc3339(),
+                "demand": { "requestorId": requestor, "properties": {} },
+                "offer": { "providerId": node(0), "properties": {} },
+            }),
+        }
+    }
+
+    /// `BlacklistReputation` backed by `MemoryStore`, which follows Tokio time with
+    /// `TokioClock`. Tests should run with `#[tokio::test(start_paused = true)]`
+    /// and move time with `advance`.
+    struct Harness {
+        reputation: BlacklistReputation,
+        store: Arc<MemoryStore>,
+        clock: Arc<dyn Clock>,
+    }
+
+    impl Harness {
+        fn new() -> Harness {
+            Harness::with_config(|_| ())
+        }
+
+        fn with_config(configure: impl FnOnce(&mut BlacklistReputationsConfig)) -> Harness {
+            Harness::with_store(Arc::new(MemoryStore::new()), configure)
+        }
+
+        fn with_store(
+            store: Arc<MemoryStore>,
+            configure: impl FnOnce(&mut BlacklistReputationsConfig),
+        ) -> Harness {
+            Harness::with_clock(store, Arc::new(TokioClock::new()), configure)
+        }
+
+        fn with_clock(
+            store: Arc<MemoryStore>,
+            clock: Arc<dyn Clock>,
+            configure: impl FnOnce(&mut BlacklistReputationsConfig),
+        ) -> Harness {
+            let reputation = BlacklistReputationBuilder::new()
+                .payment_timeout(TIMEOUT)
+                .configure(configure)
+                .store(store.clone())
+                .clock(clock.clone())
+                .build(PathBuf::new())
+                .unwrap();
+            Harness {
+                reputation,
+                store,
+                clock,
+            }
+        }
+
+        fn propose(&mut self, issuer: NodeId) -> NegotiationResult {
+            self.reputation
+                .negotiate_step(&proposal(issuer), proposal(node(0)), Score { value: 1.0 })
+                .unwrap()
+        }
+
+        fn accepted(&mut self, issuer: NodeId) -> bool {
+            matches!(self.propose(issuer), NegotiationResult::Ready { .. })
+        }
+
+        fn approve(&mut self, id: &str, requestor: NodeId) {
+            let view = agreement(id, requestor, self.clock.now());
+            self.reputation.on_agreement_approved(&view).unwrap();
+        }
+
+        fn terminate(&mut self, id: &str) {
+            self.reputation
+                .on_agreement_terminated(id, &AgreementResult::ClosedByUs)
+                .unwrap();
+        }
+
+        fn event(&mut self, id: &str, event: AgreementEvent) {
+            self.reputation.on_agreement_event(id, &event).unwrap();
+        }
+
+        /// Moves Tokio time forward and lets woken timers run.
+        async fn advance(&self, duration: Duration) {
+            tokio::time::advance(duration).await;
+            for _ in 0..10 {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        fn strikes(&self, node: &NodeId) -> u32 {
+            let state = self.reputation.locked_state();
+            state.strikes.get(node).copied().unwrap_or(0)
+        }
+
+        fn active_timers(&self) -> usize {
+            let state = self.reputation.locked_state();
+            state
+                .agreements
+                .values()
+                .filter(|record| record.timer_active())
+                .count()
+        }
+
+        /// Requestor paying for Agreement before deadline.
+        async fn clean_requestor(&mut self, id: &str, requestor: NodeId) {
+            self.approve(id, requestor);
+            self.advance(SECOND).await;
+            self.terminate(id);
+            self.advance(TIMEOUT / 2).await;
+            self.event(id, AgreementEvent::InvoicePaid);
+        }
+
+        /// Requestor not paying for Agreement.
+        async fn defaulter(&mut self, id: &str, requestor: NodeId) {
+            self.approve(id, requestor);
+            self.advance(SECOND).await;
+            self.terminate(id);
+            self.advance(TIMEOUT + SECOND).await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_clean_requestor_stays_accepted() {
+        let mut harness = Harness::new();
+        let requestor = node(1);
+
+        assert!(harness.accepted(requestor));
+        harness.clean_requestor("agreement-1", requestor).await;
+        harness.advance(TIMEOUT * 2).await;
+
+        assert!(harness.accepted(requestor));
+        assert!(!harness.reputation.is_blacklisted(&requestor));
+        assert_eq!(harness.reputation.tracked_count(), 0);
+        assert_eq!(harness.active_timers(), 0);
+        let stats = harness.reputation.node_stats(&requestor).unwrap();
+        assert_eq!((stats.total, stats.paid, stats.defaulted), (1, 1, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_defaulter_rejected() {
+        let mut harness = Harness::new();
+        let requestor = node(1);
+
+        harness.defaulter("agreement-1", requestor).await;
+
+        assert!(matches!(
+            harness.propose(requestor),
+            NegotiationResult::Reject { is_final: true, .. }
+        ));
+        assert!(harness
+            .store
+            .blacklist()
+            .iter()
+            .any(|entry| entry.node == requestor));
+        // Other requestors aren't affected.
+        assert!(harness.accepted(node(2)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_redeemed_node_accepted_after_payments() {
+        let mut harness = Harness::with_config(|config| config.redemption_payments = 2);
+        let requestor = node(1);
+
+        harness.defaulter("agreement-1", requestor).await;
+        assert!(harness.reputation.is_blacklisted(&requestor));
+
+        // Blacklisted Node is allowed on probation, so it can pay.
+        assert!(harness.accepted(requestor));
+        harness.clean_requestor("agreement-2", requestor).await;
+        assert!(harness.reputation.is_blacklisted(&requestor));
+        harness.clean_requestor("agreement-3", requestor).await;
+
+        assert!(!harness.reputation.is_blacklisted(&requestor));
+        assert!(harness.accepted(requestor));
+        assert_eq!(harness.strikes(&requestor), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unpaid_agreement_blacklists_node_after_timeout() {
+        let mut harness = Harness::new();
+        let requestor = node(1);
+
+        harness.approve("agreement-1", requestor);
+        harness.terminate("agreement-1");
+        harness.advance(TIMEOUT - SECOND).await;
+        assert!(!harness.reputation.is_blacklisted(&requestor));
+
+        harness.advance(2 * SECOND).await;
+        assert!(harness.reputation.is_blacklisted(&requestor));
+        assert_eq!(harness.reputation.blacklisted_nodes(), vec![requestor]);
+        assert_eq!(harness.reputation.tracked_count(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_payment_before_deadline_prevents_blacklisting() {
+        let mut harness = Harness::new();
+        let requestor = node(1);
+
+        harness.approve("agreement-1", requestor);
+        harness.terminate("agreement-1");
+        harness.advance(TIMEOUT - SECOND).await;
+        harness.event("agreement-1", AgreementEvent::InvoicePaid);
+        harness.advance(TIMEOUT * 2).await;
+
+        assert!(!harness.reputation.is_blacklisted(&requestor));
+        assert!(harness.store.blacklist().is_empty());
+        assert_eq!(harness.reputation.tracked_count(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_payment_right_before_deadline_stops_timer() {
+        let mut harness = Harness::new();
+        let requestor = node(1);
+
+        harness.approve("agreement-1", requestor);
+        harness.terminate("agreement-1");
+        harness.advance(TIMEOUT - Duration::from_millis(1)).await;
+        harness.event("agreement-1", AgreementEvent::InvoicePaid);
+        assert_eq!(harness.active_timers(), 0);
+        harness.advance(SECOND).await;
+
+        assert!(!harness.reputation.is_blacklisted(&requestor));
+        assert_eq!(harness.strikes(&requestor), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_payment_within_grace_after_timer_fired_reverts_strike() {
+        let mut harness = Harness::with_config(|config| config.payment_grace = Some(TIMEOUT));
+        let requestor = node(1);
+
+        harness.defaulter("agreement-1", requestor).await;
+        assert!(harness.reputation.is_blacklisted(&requestor));
+        harness.event("agreement-1", AgreementEvent::InvoicePaid);
+
+        assert!(!harness.reputation.is_blacklisted(&requestor));
+        assert_eq!(harness.strikes(&requestor), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_payment_after_timer_fired_keeps_node_blacklisted() {
+        let mut harness = Harness::new();
+        let requestor = node(1);
+
+        harness.defaulter("agreement-1", requestor).await;
+        harness.event("agreement-1", AgreementEvent::InvoicePaid);
+        harness.advance(SECOND).await;
+
+        assert!(harness.reputation.is_blacklisted(&requestor));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_malformed_proposals_accepted() {
+        let mut harness = Harness::with_config(|config| {
+            config.advertise_policy = true;
+            config.allowed_platforms = vec!["erc20-polygon-glm".to_string()];
+        });
+        let malformed = [
+            serde_json::json!("properties"),
+            serde_json::json!({ "golem": { "com": { "payment": { "chosen-platform": 7 } } } }),
+            serde_json::json!({ "golem": { "com": { "payment": { "platform": [1, 2] } } } }),
+            serde_json::json!({ "golem": { "reputation": null } }),
+        ];
+
+        for properties in malformed {
+            let mut demand = proposal(node(1));
+            demand.content.properties = properties.clone();
+            let mut offer = proposal(node(0));
+            offer.content.properties = properties;
+
+            let result = harness
+                .reputation
+                .negotiate_step(&demand, offer, Score { value: 1.0 })
+                .unwrap();
+            assert!(matches!(result, NegotiationResult::Ready { .. }));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_events_for_unknown_agreements_ignored() {
+        let mut harness = Harness::new();
+        let requestor = node(1);
+
+        for event in [
+            AgreementEvent::InvoiceAccepted,
+            AgreementEvent::InvoicePaid,
+            AgreementEvent::InvoiceRejected,
+        ] {
+            harness.event("unknown", event);
+        }
+        harness.terminate("unknown");
+        harness
+            .reputation
+            .on_agreement_terminated(
+                "",
+                &AgreementResult::Broken {
+                    reason: Some("broken".to_string()),
+                },
+            )
+            .unwrap();
+        harness.reputation.on_proposal_rejected("unknown").unwrap();
+
+        // Tracked Agreement isn't affected by events out of order.
+        harness.approve("agreement-1", requestor);
+        harness.event("agreement-1", AgreementEvent::InvoiceRejected);
+        harness.terminate("agreement-1");
+        harness.terminate("agreement-1");
+        harness.advance(TIMEOUT + SECOND).await;
+
+        assert_eq!(harness.reputation.blacklisted_nodes(), vec![requestor]);
+        assert_eq!(harness.strikes(&requestor), 1);
+    }
+
+    /// Negotiates with each banned Node and the same number of clean Nodes.
+    /// Returns time spent in `negotiate_step`.
+    fn negotiate_with_blacklisted(banned: u32) -> std::time::Duration {
+        let mut harness = Harness::new();
+        {
+            let mut state = harness.reputation.locked_state();
+            for n in 1..=banned {
+                let entry = BlacklistEntry::new(node(n), BlacklistReason::PaymentTimeout);
+                state.blacklist.insert(entry.node, entry);
+            }
+        }
+
+        let start = std::time::Instant::now();
+        for n in 1..=banned {
+            assert!(!harness.accepted(node(n)));
+            assert!(harness.accepted(node(banned + n)));
+        }
+        start.elapsed() / banned
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_large_blacklist_lookup() {
+        let small = negotiate_with_blacklisted(100);
+        let large = negotiate_with_blacklisted(10_000);
+
+        // Lookup doesn't depend on blacklist size, so time per negotiation stays about
+        // the same. Linear lookup would be around 100 times slower.
+        assert!(
+            large < small * 10 + std::time::Duration::from_micros(50),
+            "10k entries: {:?} per negotiation, 100 entries: {:?}",
+            large,
+            small
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_each_default_counted_once() {
+        let mut harness = Harness::with_config(|config| config.strikes_before_blacklist = 5);
+        let requestor = node(1);
+        let agreements = ["agreement-1", "agreement-2", "agreement-3"];
+
+        for id in agreements {
+            harness.approve(id, requestor);
+        }
+        for id in agreements {
+            harness.terminate(id);
+            harness.advance(SECOND).await;
+            harness.terminate(id);
+        }
+        harness.advance(TIMEOUT + SECOND).await;
+        for id in agreements {
+            harness.terminate(id);
+        }
+        harness.advance(TIMEOUT + SECOND).await;
+
+        assert_eq!(harness.strikes(&requestor), 3);
+        assert_eq!(
+            harness.reputation.node_stats(&requestor).unwrap().defaulted,
+            3
+        );
+        assert!(!harness.reputation.is_blacklisted(&requestor));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unblacklist_cancels_pending_payment_timer() {
+        let mut harness = Harness::new();
+        let requestor = node(1);
+
+        harness.approve("agreement-1", requestor);
+        harness.terminate("agreement-1");
+        harness.advance(TIMEOUT / 2).await;
+        harness
+            .reputation
+            .blacklist_node(requestor, "manual".to_string());
+        assert!(harness.reputation.unblacklist_node(&requestor));
+        assert_eq!(harness.active_timers(), 0);
+        harness.advance(TIMEOUT * 2).await;
+
+        assert!(!harness.reputation.is_blacklisted(&requestor));
+        assert_eq!(harness.strikes(&requestor), 0);
+        assert!(harness.store.blacklist().is_empty());
+    }
+
+    /// Wall clock, which can jump forward without Tokio time moving, the same way
+    /// as after system suspend.
+    struct SuspendedClock {
+        tokio: TokioClock,
+        suspended: Mutex<chrono::Duration>,
+    }
+
+    impl SuspendedClock {
+        fn new() -> SuspendedClock {
+            SuspendedClock {
+                tokio: TokioClock::new(),
+                suspended: Mutex::new(chrono::Duration::zero()),
+            }
+        }
+
+        fn suspend(&self, duration: Duration) {
+            *self.suspended.lock().unwrap() += chrono::Duration::from_std(duration).unwrap();
+        }
+    }
+
+    impl Clock for SuspendedClock {
+        fn now(&self) -> DateTime<Utc> {
+            self.tokio.now() + *self.suspended.lock().unwrap()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_suspend_counts_towards_payment_deadline() {
+        let clock = Arc::new(SuspendedClock::new());
+        let store = Arc::new(MemoryStore::new());
+        let mut harness = Harness::with_clock(store, clock.clone(), |_| ());
+        let requestor = node(1);
+
+        harness.approve("agreement-1", requestor);
+        harness.terminate("agreement-1");
+        clock.suspend(TIMEOUT / 2);
+        harness.advance(TIMEOUT / 2 - 2 * SECOND).await;
+        assert!(!harness.reputation.is_blacklisted(&requestor));
+
+        // Deadline passed before Tokio timer would expire.
+        harness.advance(3 * SECOND).await;
+        assert!(harness.reputation.is_blacklisted(&requestor));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deadline_passed_during_suspend_noticed_after_wake_up() {
+        let clock = Arc::new(SuspendedClock::new());
+        let store = Arc::new(MemoryStore::new());
+        let mut harness = Harness::with_clock(store, clock.clone(), |_| ());
+        let requestor = node(1);
+
+        harness.approve("agreement-1", requestor);
+        harness.terminate("agreement-1");
+        clock.suspend(TIMEOUT * 10);
+        harness.advance(DEADLINE_CHECK_INTERVAL + SECOND).await;
+
+        assert!(harness.reputation.is_blacklisted(&requestor));
+        assert_eq!(harness.strikes(&requestor), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_future_approved_date_clamped() {
+        let mut harness = Harness::new();
+        let requestor = node(1);
+        let now = harness.clock.now();
+        let approved = [
+            ("agreement-1", now + chrono::Duration::days(365)),
+            ("agreement-2", DateTime::<Utc>::MAX_UTC),
+            ("agreement-3", now + chrono::Duration::seconds(30)),
+        ];
+
+        for (id, date) in approved {
+            let view = agreement(id, requestor, date);
+            harness.reputation.on_agreement_approved(&view).unwrap();
+        }
+        let signed = |id: &str| harness.reputation.locked_state().agreements[id].signed;
+        assert_eq!(signed("agreement-1"), now);
+        assert_eq!(signed("agreement-2"), now);
+        // Dates within `max_clock_skew` are kept.
+        assert_eq!(signed("agreement-3"), approved[2].1);
+
+        harness.terminate("agreement-2");
+        harness.advance(TIMEOUT + SECOND).await;
+        assert!(harness.reputation.is_blacklisted(&requestor));
+    }
+
+    #[test]
+    fn test_parse_node_id_in_any_case() {
+        let expected = parse_node_id("0xabcdef0123456789abcdef0123456789abcdef01").unwrap();
+        for written in [
+            "0xABCDEF0123456789ABCDEF0123456789ABCDEF01",
+            "0XAbCdEf0123456789aBcDeF0123456789AbCdEf01",
+            "abcdef0123456789ABCDEF0123456789abcdef01",
+            "  0xabcdef0123456789abcdef0123456789abcdef01\n",
+        ] {
+            assert_eq!(parse_node_id(written).unwrap(), expected, "{}", written);
+        }
+        assert!(parse_node_id("0xabcdef").is_err());
+        assert!(parse_node_id("0xZZcdef0123456789abcdef0123456789abcdef01").is_err());
+
+        let mut items = vec![
+            serde_json::json!("0xABCDEF0123456789ABCDEF0123456789ABCDEF01"),
+            serde_json::json!({ "node": "ABCDEF0123456789ABCDEF0123456789ABCDEF01" }),
+        ];
+        for item in &mut items {
+            normalize_item(item);
+        }
+        assert_eq!(items[0], serde_json::json!(expected.to_string()));
+        assert_eq!(items[1]["node"], serde_json::json!(expected.to_string()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_malformed_agreement_not_tracked() {
+        let mut harness = Harness::new();
+        let malformed = [
+            serde_json::Value::Null,
+            serde_json::json!("agreement"),
+            serde_json::json!({}),
+            serde_json::json!({ "demand": null }),
+            serde_json::json!({ "demand": { "requestorId": 7 } }),
+            serde_json::json!({ "demand": { "requestorId": "0xnot-a-node" } }),
+            serde_json::json!({ "demand": { "requestorId": ["0x01"] } }),
+        ];
+
+        for (i, json) in malformed.into_iter().enumerate() {
+            let id = format!("agreement-{}", i);
+            let view = AgreementView {
+                id: id.clone(),
+                json,
+            };
+            harness.reputation.on_agreement_approved(&view).unwrap();
+            harness.terminate(&id);
+            harness.event(&id, AgreementEvent::InvoicePaid);
+        }
+
+        assert_eq!(harness.reputation.tracked_count(), 0);
+        harness.advance(TIMEOUT + SECOND).await;
+        assert!(harness.reputation.blacklisted_nodes().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_invalid_approved_date() {
+        let mut view = agreement("agreement-1", node(1), Utc::now());
+        view.json["approved_date"] = serde_json::json!("yesterday");
+
+        let mut harness = Harness::new();
+        harness.reputation.on_agreement_approved(&view).unwrap();
+        assert_eq!(harness.reputation.tracked_count(), 1);
+
+        let mut harness =
+            Harness::with_config(|config| config.missing_approved_date = MissingApprovedDate::Skip);
+        harness.reputation.on_agreement_approved(&view).unwrap();
+        assert_eq!(harness.reputation.tracked_count(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_duplicate_approval_ignored() {
+        let mut harness = Harness::with_config(|config| config.strikes_before_blacklist = 5);
+        let requestor = node(1);
+
+        harness.approve("agreement-1", requestor);
+        harness.advance(SECOND).await;
+        harness.approve("agreement-1", requestor);
+        harness.terminate("agreement-1");
+        harness.approve("agreement-1", requestor);
+
+        assert_eq!(harness.reputation.tracked_count(), 1);
+        assert_eq!(harness.active_timers(), 1);
+        let record = harness.reputation.locked_state().agreements["agreement-1"].clone();
+        assert!(record.terminated.is_some());
+
+        harness.advance(TIMEOUT + SECOND).await;
+        assert_eq!(harness.strikes(&requestor), 1);
+        assert_eq!(harness.reputation.node_stats(&requestor).unwrap().total, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_one_timer_per_terminated_agreement() {
+        let mut harness = Harness::with_config(|config| config.strikes_before_blacklist = 5);
+        let requestor = node(1);
+        let timers = |harness: &Harness| {
+            let dumped = harness.reputation.dump_state()["active_timers"].as_u64();
+            assert_eq!(dumped, Some(harness.active_timers() as u64));
+            harness.active_timers()
+        };
+
+        for id in ["agreement-1", "agreement-2", "agreement-3", "agreement-4"] {
+            harness.approve(id, requestor);
+        }
+        assert_eq!(timers(&harness), 0);
+        for id in ["agreement-1", "agreement-2", "agreement-3"] {
+            harness.terminate(id);
+        }
+        assert_eq!(timers(&harness), 3);
+
+        harness.event("agreement-1", AgreementEvent::InvoicePaid);
+        assert_eq!(timers(&harness), 2);
+        harness.event("agreement-2", AgreementEvent::InvoiceRejected);
+        assert_eq!(timers(&harness), 1);
+
+        harness.advance(TIMEOUT + SECOND).await;
+        assert_eq!(timers(&harness), 0);
+        assert_eq!(harness.reputation.tracked_count(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_late_payment_reverts_only_counted_strike() {
+        let mut harness = Harness::with_config(|config| {
+            config.strikes_before_blacklist = 5;
+            config.first_offense_action = FirstOffenseAction::Forgive;
+            config.payment_grace = Some(TIMEOUT * 10);
+            config.reputation_penalty = 0.5;
+        });
+        let requestor = node(1);
+
+        // First default is forgiven, the second one gives strike.
+        harness.defaulter("agreement-1", requestor).await;
+        assert_eq!(harness.strikes(&requestor), 0);
+        harness.defaulter("agreement-2", requestor).await;
+        assert_eq!(harness.strikes(&requestor), 1);
+        let factor = harness.reputation.locked_state().reputation[&requestor];
+
+        harness.event("agreement-1", AgreementEvent::InvoicePaid);
+        assert_eq!(harness.strikes(&requestor), 1);
+        assert_eq!(
+            harness.reputation.locked_state().reputation[&requestor],
+            factor
+        );
+        assert_eq!(
+            harness.reputation.node_stats(&requestor).unwrap().defaulted,
+            1
+        );
+
+        harness.event("agreement-2", AgreementEvent::InvoicePaid);
+        assert_eq!(harness.strikes(&requestor), 0);
+        assert_eq!(
+            harness.reputation.node_stats(&requestor).unwrap().defaulted,
+            0
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_blacklist_and_graylist_expire_with_clock() {
+        let hour = Duration::from_secs(3600);
+        let mut harness = Harness::with_config(|config| {
+            config.blacklist_ttl = Some(hour);
+            config.graylist_ttl = Some(hour);
+        });
+        let (banned, graylisted) = (node(1), node(2));
+        harness.defaulter("agreement-1", banned).await;
+        let config = harness.reputation.config.clone();
+        harness
+            .reputation
+            .locked_state()
+            .graylist
+            .insert(graylisted, harness.clock.now());
+
+        harness.advance(hour / 2).await;
+        let now = harness.clock.now();
+        assert!(harness.reputation.is_blacklisted(&banned));
+        assert!(!harness.accepted(banned));
+        assert!(harness
+            .reputation
+            .locked_state()
+            .is_graylisted(&graylisted, now, &config));
+
+        harness.advance(hour).await;
+        let now = harness.clock.now();
+        assert!(!harness.reputation.is_blacklisted(&banned));
+        assert!(harness.accepted(banned));
+        assert!(!harness
+            .reputation
+            .locked_state()
+            .is_graylisted(&graylisted, now, &config));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_abandoned_negotiations_use_clock() {
+        let day = Duration::from_secs(24 * 3600);
+        for blacklist in [false, true] {
+            let mut harness = Harness::with_config(|config| {
+                config.abandon_threshold = 1;
+                config.abandon_blacklist = blacklist;
+            });
+            let requestor = node(1);
+            harness.advance(day).await;
+
+            assert!(harness.accepted(requestor));
+            harness
+                .reputation
+                .on_proposal_rejected(&proposal(requestor).id)
+                .unwrap();
+
+            let now = harness.clock.now();
+            let state = harness.reputation.locked_state();
+            assert_eq!(state.stats[&requestor].last_seen, Some(now));
+            if blacklist {
+                assert_eq!(state.blacklist[&requestor].added, now);
+            } else {
+                assert_eq!(state.graylist[&requestor], now);
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_terminated_agreements_untracked_without_timeout_blacklist() {
+        let day = Duration::from_secs(24 * 3600);
+        let mut harness = Harness::with_config(|config| {
+            config.enable_timeout_blacklist = false;
+            config.max_terminated_age = day;
+        });
+        let requestor = node(1);
+
+        harness.approve("agreement-1", requestor);
+        harness.approve("agreement-2", requestor);
+        harness.terminate("agreement-1");
+        assert_eq!(harness.reputation.next_deadline(), None);
+        assert_eq!(harness.active_timers(), 0);
+
+        harness.advance(day + TIMEOUT).await;
+        // Agreement, which wasn't terminated, is still tracked.
+        assert_eq!(harness.reputation.tracked_count(), 1);
+        assert!(harness
+            .reputation
+            .locked_state()
+            .agreements
+            .contains_key("agreement-2"));
+        assert!(!harness.reputation.is_blacklisted(&requestor));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_whitelisted_node_exempt_from_platform_allowlist() {
+        let trusted = node(1);
+        let store = Arc::new(MemoryStore::with_whitelist(vec![trusted]));
+        let mut harness = Harness::with_store(store, |config| {
+            config.allowed_platforms = vec!["erc20-polygon-glm".to_string()];
+        });
+
+        for requestor in [trusted, node(2)] {
+            let mut demand = proposal(requestor);
+            demand.content.properties = serde_json::json!({
+                "golem": { "com": { "payment": { "chosen-platform": "erc20-goerli-tglm" } } }
+            });
+            let result = harness
+                .reputation
+                .negotiate_step(&demand, proposal(node(0)), Score { value: 1.0 })
+                .unwrap();
+            assert_eq!(
+                matches!(result, NegotiationResult::Ready { .. }),
+                requestor == trusted
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_outcomes_recorded_for_simulation() {
+        let mut harness = Harness::with_config(|config| {
+            config.record_outcomes = true;
+            config.payment_grace = Some(TIMEOUT);
+        });
+        let (clean, late, rejecting) = (node(1), node(2), node(3));
+
+        harness.clean_requestor("agreement-1", clean).await;
+        harness.defaulter("agreement-2", late).await;
+        harness.event("agreement-2", AgreementEvent::InvoicePaid);
+        harness.approve("agreement-3", rejecting);
+        harness.terminate("agreement-3");
+        harness.event("agreement-3", AgreementEvent::InvoiceRejected);
+        harness.reputation.shutdown().await.unwrap();
+
+        let mut history = harness.store.load_history().unwrap();
+        history.sort_by(|a, b| a.id.cmp(&b.id));
+        let outcomes = history
+            .iter()
+            .map(|outcome| {
+                (
+                    outcome.node,
+                    outcome.paid.is_some(),
+                    outcome.rejected.is_some(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outcomes,
+            vec![
+                (clean, true, false),
+                (late, true, false),
+                (rejecting, false, true)
+            ]
+        );
+
+        let report = crate::simulation::simulate(&history, &harness.reputation.config);
+        assert_eq!(report.agreements, 3);
+        assert_eq!(report.total_blacklisted(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_partial_payment_counted_separately() {
+        let mut harness = Harness::with_config(|config| config.record_outcomes = true);
+        let requestor = node(1);
+        let mut view = agreement("agreement-1", requestor, harness.clock.now());
+        view.json["offer"]["properties"] = serde_json::json!({
+            "golem": {
+                "com": {
+                    "usage": { "vector": ["golem.usage.duration_sec"] },
+                    "pricing": { "model": { "linear": { "coeffs": [0.0, 10.0] } } },
+                }
+            }
+        });
+        harness.reputation.on_agreement_approved(&view).unwrap();
+        harness.terminate("agreement-1");
+        harness.reputation.report_payment("agreement-1", 1.0);
+
+        let state = harness.reputation.locked_state();
+        assert_eq!(state.metrics.partial_payments.get(), 1);
+        assert_eq!(state.metrics.invoices_rejected.get(), 0);
+        assert_eq!(state.strikes.get(&requestor), Some(&1));
+        let outcome = state.history.last().unwrap();
+        assert_eq!(outcome.amount_paid, Some(1.0));
+        assert_eq!(outcome.amount, Some(10.0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_import_snapshot_restores_without_new_bans() {
+        let mut source = Harness::with_config(|config| config.reputation_penalty = 0.5);
+        for n in 1..=3 {
+            source.defaulter(&format!("agreement-{}", n), node(n)).await;
+        }
+        source
+            .reputation
+            .locked_state()
+            .abandon_streak
+            .insert(node(4), 2);
+        let snapshot = source.reputation.export_snapshot();
+
+        let mut target = Harness::with_config(|config| config.max_blacklist_size = Some(1));
+        let (sender, mut events) = mpsc::channel(10);
+        target.reputation = target.reputation.with_events(sender);
+        target.reputation.import_snapshot(snapshot).unwrap();
+
+        assert_eq!(target.reputation.blacklisted_nodes().len(), 3);
+        assert!(events.try_recv().is_err());
+        let state = target.reputation.locked_state();
+        assert_eq!(state.reputation[&node(1)], 0.5);
+        assert_eq!(state.abandon_streak[&node(4)], 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replace_merge_keeps_remote_entries() {
+        let mut harness = Harness::new();
+        harness.defaulter("agreement-1", node(1)).await;
+        harness.defaulter("agreement-2", node(2)).await;
+        {
+            let mut state = harness.reputation.locked_state();
+            let remote = BlacklistEntry::new(node(3), BlacklistReason::Unknown);
+            state.blacklist.insert(node(3), remote);
+            state.remote.insert(node(3));
+        }
+        let (sender, mut events) = mpsc::channel(10);
+        harness.reputation = harness.reputation.with_events(sender);
+
+        let imported = [2, 3, 4]
+            .into_iter()
+            .map(|n| BlacklistEntry::new(node(n), BlacklistReason::Manual("fleet".to_string())))
+            .collect();
+        harness
+            .reputation
+            .merge_blacklist(imported, MergeMode::Replace);
+
+        let mut nodes = harness.reputation.blacklisted_nodes();
+        nodes.sort_by_key(|node| node.to_string());
+        assert_eq!(nodes, vec![node(2), node(3), node(4)]);
+        let state = harness.reputation.locked_state();
+        assert!(state.blacklist[&node(2)].is_manual());
+        // Remote entry isn't replaced by imported one.
+        assert!(!state.blacklist[&node(3)].is_manual());
+        assert!(events.try_recv().is_err());
+    }
+}
@@ -0,0 +1,65 @@
+//! Source of current time for payment deadlines.
+use chrono::{DateTime, Utc};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// Wall clock time. Used by default.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock following Tokio time. When Tokio time is paused (for example in
+/// `#[tokio::test(start_paused = true)]`), it moves only with `tokio::time::advance`,
+/// together with payment timers.
+pub struct TokioClock {
+    start: DateTime<Utc>,
+    instant: tokio::time::Instant,
+}
+
+impl TokioClock {
+    /// Must be created within Tokio runtime.
+    pub fn new() -> TokioClock {
+        TokioClock {
+            start: Utc::now(),
+            instant: tokio::time::Instant::now(),
+        }
+    }
+}
+
+impl Default for TokioClock {
+    fn default() -> Self {
+        TokioClock::new()
+    }
+}
+
+impl Clock for TokioClock {
+    fn now(&self) -> DateTime<Utc> {
+        let elapsed = chrono::Duration::from_std(self.instant.elapsed()).unwrap_or_default();
+        self.start + elapsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tokio_clock_follows_paused_time() {
+        let clock = TokioClock::new();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+
+        tokio::time::advance(Duration::from_secs(90)).await;
+        assert_eq!(clock.now() - start, chrono::Duration::seconds(90));
+
+        tokio::time::sleep(Duration::from_secs(3600)).await;
+        assert_eq!(clock.now() - start, chrono::Duration::seconds(3690));
+    }
+}
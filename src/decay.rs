@@ -0,0 +1,111 @@
+//! Fading of reputation penalty after Node defaults on payment.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DecayModel {
+    /// Penalty disappears linearly over `period`.
+    Linear,
+    /// Penalty halves every `period`.
+    Exponential,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DecayConfig {
+    pub model: DecayModel,
+    /// Duration of linear decay or half-life of exponential decay.
+    #[serde(with = "humantime_serde")]
+    pub period: Duration,
+    /// Score factor of Node right after default.
+    #[serde(default = "default_floor")]
+    pub floor: f64,
+}
+
+fn default_floor() -> f64 {
+    0.5
+}
+
+/// Score factor of Node, which last defaulted at `last_default`. Factor starts
+/// at `floor` and goes back to 1.0 according to decay model.
+pub fn current_factor(last_default: DateTime<Utc>, now: DateTime<Utc>, cfg: &DecayConfig) -> f64 {
+    let elapsed = (now - last_default)
+        .to_std()
+        .unwrap_or_default()
+        .as_secs_f64();
+    let period = cfg.period.as_secs_f64();
+
+    let remaining = if period <= 0.0 {
+        0.0
+    } else {
+        match cfg.model {
+            DecayModel::Linear => (1.0 - elapsed / period).max(0.0),
+            DecayModel::Exponential => 0.5f64.powf(elapsed / period),
+        }
+    };
+    1.0 - (1.0 - cfg.floor.clamp(0.0, 1.0)) * remaining
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(model: DecayModel, floor: f64) -> DecayConfig {
+        DecayConfig {
+            model,
+            period: Duration::from_secs(3600),
+            floor,
+        }
+    }
+
+    fn factor_after(secs: i64, cfg: &DecayConfig) -> f64 {
+        let last_default = Utc::now();
+        current_factor(
+            last_default,
+            last_default + chrono::Duration::seconds(secs),
+            cfg,
+        )
+    }
+
+    #[test]
+    fn test_factor_right_after_default_is_floor() {
+        for model in [DecayModel::Linear, DecayModel::Exponential] {
+            let cfg = config(model, 0.5);
+            assert_eq!(factor_after(0, &cfg), 0.5);
+            // Clock going backwards doesn't lower factor below floor.
+            assert_eq!(factor_after(-3600, &cfg), 0.5);
+        }
+    }
+
+    #[test]
+    fn test_factor_at_half_life() {
+        let cfg = config(DecayModel::Exponential, 0.5);
+        assert!((factor_after(3600, &cfg) - 0.75).abs() < 1e-9);
+        assert!((factor_after(7200, &cfg) - 0.875).abs() < 1e-9);
+
+        let cfg = config(DecayModel::Linear, 0.5);
+        assert!((factor_after(1800, &cfg) - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_factor_clamped() {
+        let linear = config(DecayModel::Linear, 0.5);
+        assert_eq!(factor_after(3600, &linear), 1.0);
+        assert_eq!(factor_after(100 * 3600, &linear), 1.0);
+
+        let exponential = config(DecayModel::Exponential, 0.5);
+        let factor = factor_after(1000 * 3600, &exponential);
+        assert!(factor <= 1.0 && factor > 0.999);
+
+        // Floor outside of [0.0, 1.0] is clamped.
+        assert_eq!(factor_after(0, &config(DecayModel::Linear, -1.0)), 0.0);
+        assert_eq!(factor_after(0, &config(DecayModel::Linear, 2.0)), 1.0);
+
+        let zero_period = DecayConfig {
+            period: Duration::ZERO,
+            ..config(DecayModel::Exponential, 0.5)
+        };
+        assert_eq!(factor_after(0, &zero_period), 1.0);
+    }
+}
@@ -1 +1,10 @@
-mod blacklist;
\ No newline at end of file
+pub mod blacklist;
+pub mod clock;
+pub mod decay;
+pub mod logging;
+pub mod metrics;
+pub mod remote;
+pub mod simulation;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod storage;
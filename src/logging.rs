@@ -0,0 +1,147 @@
+//! Plugin log file setup.
+//!
+//! Logs go to file in working directory, to local syslog (`/dev/log`, read by journald
+//! on systemd hosts) or to both, depending on `LogTarget`.
+//!
+//! With `LogFormat::Json` each line is JSON object with `timestamp`, `level`, `target`
+//! and `message`. Key decision events (blacklisting, rejecting, strikes) attach additional
+//! fields like `event`, `node`, `agreement_id` and `reason`, so enforcement actions can
+//! be queried in log pipeline without parsing messages.
+use chrono::Utc;
+use flexi_logger::writers::{Syslog, SyslogFacility, SyslogWriter};
+use flexi_logger::{Cleanup, Criterion, DeferredNow, FileSpec, Logger, LoggerHandle, Naming};
+use log::kv::{self, VisitSource};
+use log::Record;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(anyhow::anyhow!("Unknown log format: {}", s)),
+        }
+    }
+}
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogTarget {
+    #[default]
+    File,
+    Syslog,
+    Both,
+}
+
+impl FromStr for LogTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "file" => Ok(LogTarget::File),
+            "syslog" => Ok(LogTarget::Syslog),
+            "both" => Ok(LogTarget::Both),
+            _ => Err(anyhow::anyhow!("Unknown log target: {}", s)),
+        }
+    }
+}
+
+/// Log file rotation. Without `max_size` log file is never rotated.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rotation {
+    /// Size in bytes, after which log file is rotated.
+    pub max_size: Option<u64>,
+    /// Number of rotated log files to keep. All are kept, if not set.
+    pub keep_files: Option<usize>,
+}
+
+/// Starts logging to `basename` file in `directory` and/or syslog. `format` and
+/// `rotation` apply only to log file.
+pub fn start_logger(
+    directory: &Path,
+    basename: String,
+    target: LogTarget,
+    format: LogFormat,
+    rotation: Rotation,
+) -> anyhow::Result<LoggerHandle> {
+    let file_spec = FileSpec::default().directory(directory).basename(basename);
+    let logger = Logger::try_with_env()?;
+    let mut logger = match target {
+        LogTarget::File => logger.log_to_file(file_spec),
+        LogTarget::Syslog => logger.log_to_writer(syslog_writer()?),
+        LogTarget::Both => logger.log_to_file_and_writer(file_spec, syslog_writer()?),
+    };
+    if format == LogFormat::Json {
+        logger = logger.format_for_files(json_format);
+    }
+
+    if let Some(max_size) = rotation.max_size {
+        let cleanup = match rotation.keep_files {
+            Some(keep_files) => Cleanup::KeepLogFiles(keep_files),
+            None => Cleanup::Never,
+        };
+        logger = logger.rotate(Criterion::Size(max_size), Naming::Numbers, cleanup);
+    }
+
+    let handle = logger.start()?;
+    if rotation.max_size.is_none() && rotation.keep_files.is_some() {
+        log::warn!("`log_keep_files` has no effect without `log_max_size`.");
+    }
+    Ok(handle)
+}
+
+fn syslog_writer() -> anyhow::Result<Box<SyslogWriter>> {
+    Ok(SyslogWriter::try_new_bsd(
+        SyslogFacility::SystemDaemons,
+        None,
+        log::LevelFilter::Trace,
+        Syslog::try_datagram(SYSLOG_SOCKET)?,
+    )?)
+}
+
+/// Collects key-values attached to log record.
+struct Fields<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.insert(
+            key.to_string(),
+            serde_json::Value::String(value.to_string()),
+        );
+        Ok(())
+    }
+}
+
+pub fn json_format(
+    w: &mut dyn std::io::Write,
+    _now: &mut DeferredNow,
+    record: &Record,
+) -> Result<(), std::io::Error> {
+    let mut object = serde_json::Map::new();
+    object.insert("timestamp".into(), Utc::now().to_rfc3339().into());
+    object.insert("level".into(), record.level().as_str().into());
+    object.insert("target".into(), record.target().into());
+    object.insert("message".into(), record.args().to_string().into());
+    record
+        .key_values()
+        .visit(&mut Fields(&mut object))
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    write!(w, "{}", serde_json::Value::Object(object))
+}
@@ -0,0 +1,89 @@
+//! Metrics describing enforcement behavior of the plugin.
+//!
+//! Metrics are registered in dedicated `prometheus::Registry`, which can be obtained
+//! with `BlacklistReputation::metrics_registry`. Plugin doesn't serve metrics by itself,
+//! embedding application should expose them on its own scrape endpoint, for example:
+//!
+//! ```ignore
+//! let encoder = prometheus::TextEncoder::new();
+//! let text = encoder.encode_to_string(&reputation.metrics_registry().gather())?;
+//! // Return `text` from `/metrics` HTTP handler.
+//! ```
+//!
+//! All metrics names are prefixed with `ya_reputation_`.
+use prometheus::{IntCounter, IntGauge, Registry};
+
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    pub agreements_tracked: IntCounter,
+    pub invoices_paid: IntCounter,
+    pub invoices_rejected: IntCounter,
+    pub partial_payments: IntCounter,
+    pub payment_timeouts: IntCounter,
+    pub blacklist_size: IntGauge,
+    pub remote_cache_hits: IntCounter,
+    pub remote_cache_misses: IntCounter,
+}
+
+impl Metrics {
+    pub fn new() -> anyhow::Result<Metrics> {
+        let registry = Registry::new_custom(Some("ya_reputation".to_string()), None)?;
+
+        let agreements_tracked = IntCounter::new(
+            "agreements_tracked_total",
+            "Number of Agreements tracked for payment",
+        )?;
+        let invoices_paid = IntCounter::new("invoices_paid_total", "Number of paid Invoices")?;
+        let invoices_rejected =
+            IntCounter::new("invoices_rejected_total", "Number of rejected Invoices")?;
+        let partial_payments = IntCounter::new(
+            "partial_payments_total",
+            "Number of Invoices paid below amount due",
+        )?;
+        let payment_timeouts = IntCounter::new(
+            "payment_timeouts_total",
+            "Number of Agreements not paid in payment timeout",
+        )?;
+        let blacklist_size = IntGauge::new("blacklist_size", "Number of blacklisted Nodes")?;
+        let remote_cache_hits = IntCounter::new(
+            "remote_cache_hits_total",
+            "Number of remote blacklist synchronizations, which found blacklist unchanged",
+        )?;
+        let remote_cache_misses = IntCounter::new(
+            "remote_cache_misses_total",
+            "Number of remote blacklist synchronizations, which parsed changed blacklist",
+        )?;
+
+        registry.register(Box::new(agreements_tracked.clone()))?;
+        registry.register(Box::new(invoices_paid.clone()))?;
+        registry.register(Box::new(invoices_rejected.clone()))?;
+        registry.register(Box::new(partial_payments.clone()))?;
+        registry.register(Box::new(payment_timeouts.clone()))?;
+        registry.register(Box::new(blacklist_size.clone()))?;
+        registry.register(Box::new(remote_cache_hits.clone()))?;
+        registry.register(Box::new(remote_cache_misses.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            agreements_tracked,
+            invoices_paid,
+            invoices_rejected,
+            partial_payments,
+            payment_timeouts,
+            blacklist_size,
+            remote_cache_hits,
+            remote_cache_misses,
+        })
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new().expect("Metrics definitions are valid")
+    }
+}
@@ -0,0 +1,173 @@
+//! Blacklist shared by fleet of providers and served over HTTP(S).
+//!
+//! Response body has the same structure as local blacklist file: list of blacklist
+//! entries or (legacy) list of Node ids. Format is chosen based on `Content-Type` header:
+//! `application/json` is parsed as JSON, any other content type (preferably
+//! `application/yaml`) is parsed as YAML.
+//!
+//! Server should return `ETag` or `Last-Modified` header. They are sent back in
+//! `If-None-Match` and `If-Modified-Since` on re-sync, so unchanged blacklist isn't
+//! downloaded again. Servers ignoring conditional requests return full body; it is
+//! compared with cached content and parsed only if it has changed.
+//!
+//! Last fetched content is kept in `RemoteCache`, which is stored in working directory,
+//! so remote blacklist is enforced right after restart, before first re-sync succeeds.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use url::Url;
+
+use ya_client_model::NodeId;
+
+use crate::blacklist::{BlacklistEntry, BlacklistFile};
+use crate::storage::{Format, OnParseError};
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+const FETCH_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Last successfully fetched remote blacklist.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RemoteCache {
+    pub url: Url,
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub last_modified: Option<String>,
+    pub format: Format,
+    pub content: String,
+}
+
+pub struct RemoteBlacklist {
+    client: reqwest::Client,
+    url: Url,
+    cache: Option<RemoteCache>,
+    on_parse_error: OnParseError,
+}
+
+impl RemoteBlacklist {
+    pub fn new(url: Url, on_parse_error: OnParseError) -> anyhow::Result<RemoteBlacklist> {
+        let client = reqwest::Client::builder().timeout(FETCH_TIMEOUT).build()?;
+        Ok(RemoteBlacklist {
+            client,
+            url,
+            cache: None,
+            on_parse_error,
+        })
+    }
+
+    /// Restores content fetched by previous run. Cache of different url is ignored.
+    /// Returns blacklist parsed from cache.
+    pub fn restore_cache(
+        &mut self,
+        cache: RemoteCache,
+    ) -> anyhow::Result<HashMap<NodeId, BlacklistEntry>> {
+        if cache.url != self.url {
+            anyhow::bail!("Cached blacklist was fetched from {}", cache.url);
+        }
+        let blacklist = parse(cache.format, &cache.content, self.on_parse_error)?;
+        self.cache = Some(cache);
+        Ok(blacklist)
+    }
+
+    pub fn cache(&self) -> Option<&RemoteCache> {
+        self.cache.as_ref()
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Downloads blacklist retrying on failure. Returns `None` if blacklist
+    /// didn't change since last fetch.
+    pub async fn fetch(&mut self) -> anyhow::Result<Option<HashMap<NodeId, BlacklistEntry>>> {
+        let mut attempt = 1;
+        loop {
+            match self.fetch_once().await {
+                Ok(blacklist) => return Ok(blacklist),
+                Err(e) if attempt < FETCH_ATTEMPTS => {
+                    log::debug!(
+                        "Fetching remote blacklist from {} failed (attempt {}/{}): {}",
+                        self.url,
+                        attempt,
+                        FETCH_ATTEMPTS,
+                        e
+                    );
+                    attempt += 1;
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn fetch_once(&mut self) -> anyhow::Result<Option<HashMap<NodeId, BlacklistEntry>>> {
+        let mut request = self.client.get(self.url.clone());
+        if let Some(cache) = &self.cache {
+            if let Some(etag) = &cache.etag {
+                request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &cache.last_modified {
+                request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let response = request.send().await?;
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        let etag = header(reqwest::header::ETAG);
+        let last_modified = header(reqwest::header::LAST_MODIFIED);
+
+        let format = match response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+        {
+            Some(content_type) if content_type.starts_with("application/json") => Format::Json,
+            _ => Format::Yaml,
+        };
+
+        let content = response.text().await?;
+        let unchanged = self
+            .cache
+            .as_ref()
+            .map(|cache| cache.format == format && cache.content == content)
+            .unwrap_or(false);
+        let blacklist = match unchanged {
+            true => None,
+            false => Some(parse(format, &content, self.on_parse_error)?),
+        };
+
+        self.cache = Some(RemoteCache {
+            url: self.url.clone(),
+            etag,
+            last_modified,
+            format,
+            content,
+        });
+        Ok(blacklist)
+    }
+}
+
+fn parse(
+    format: Format,
+    content: &str,
+    on_parse_error: OnParseError,
+) -> anyhow::Result<HashMap<NodeId, BlacklistEntry>> {
+    let file = BlacklistFile::parse(format, content, on_parse_error)?;
+    // Whole remote blacklist is enforced the same way, so sections are merged.
+    let static_blacklist = file.static_blacklist();
+    let (mut blacklist, _) = file.into_blacklist();
+    blacklist.extend(static_blacklist);
+    Ok(blacklist)
+}
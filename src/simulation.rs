@@ -0,0 +1,197 @@
+//! Offline replay of recorded Agreement outcomes, which shows how candidate
+//! configuration would classify Nodes before it is deployed.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use ya_client_model::NodeId;
+
+use crate::blacklist::{
+    BlacklistReason, BlacklistReputationsConfig, BlacklistState, TrackedAgreement,
+};
+
+/// Recorded history of single Agreement. Plugin appends outcomes to history
+/// with `record_outcomes` enabled.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AgreementOutcome {
+    pub id: String,
+    pub node: NodeId,
+    pub signed: DateTime<Utc>,
+    /// Agreements, which were never terminated, are not replayed.
+    pub terminated: Option<DateTime<Utc>>,
+    /// Time of payment. None if Agreement was never paid.
+    pub paid: Option<DateTime<Utc>>,
+    /// Time of Invoice rejection.
+    pub rejected: Option<DateTime<Utc>>,
+    /// Amount due for the Agreement, used by `min_unpaid_amount`.
+    #[serde(default)]
+    pub amount: Option<f64>,
+    /// Amount reported as paid. None if unknown.
+    #[serde(default)]
+    pub amount_paid: Option<f64>,
+    #[serde(default)]
+    pub policy: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SimulationReport {
+    /// Number of replayed Agreements.
+    pub agreements: usize,
+    /// Strikes given per reason.
+    pub strikes: BTreeMap<String, usize>,
+    /// Nodes blacklisted at the end of replay per reason.
+    pub blacklisted: BTreeMap<String, usize>,
+    /// Nodes removed from blacklist thanks to `redemption_payments`.
+    pub redeemed: usize,
+}
+
+impl SimulationReport {
+    pub fn total_blacklisted(&self) -> usize {
+        self.blacklisted.values().sum()
+    }
+}
+
+/// Replays `history` in order of events (payments and defaults) using the same rules
+/// as running plugin. Time of each event is used as current time, so blacklist entries
+/// expire and redemption cooldowns pass like they would in running plugin.
+/// Config with `dry_run` enabled never blacklists, so it should be disabled
+/// in candidate config.
+pub fn simulate(
+    history: &[AgreementOutcome],
+    cfg: &BlacklistReputationsConfig,
+) -> SimulationReport {
+    let mut events = history
+        .iter()
+        .filter_map(|outcome| event(outcome, cfg).map(|(at, reason)| (at, reason, outcome)))
+        .collect::<Vec<_>>();
+    events.sort_by_key(|(at, _, _)| *at);
+
+    let mut state = BlacklistState::default();
+    let mut report = SimulationReport::default();
+
+    for (at, reason, outcome) in events {
+        state.remove_expired(at, cfg);
+        report.agreements += 1;
+        state.stats.entry(outcome.node).or_default().total += 1;
+
+        let mut record = TrackedAgreement::new(outcome.id.clone(), outcome.node, outcome.signed);
+        record.terminated = outcome.terminated;
+        record.policy = outcome.policy.clone();
+
+        match reason {
+            Some(reason) => {
+                *report.strikes.entry(reason.to_string()).or_default() += 1;
+                // Amount of agreement is counted by `add_strike` using pricing, which
+                // isn't part of recorded outcome.
+                *state.unpaid.entry(outcome.node).or_default() += outcome.amount.unwrap_or(0.0);
+                state.add_strike(&record, reason, "simulation", at, cfg);
+            }
+            None => {
+                if state.add_payment(outcome.node, at, cfg) {
+                    report.redeemed += 1;
+                }
+            }
+        }
+    }
+
+    for entry in state.blacklist.values() {
+        *report
+            .blacklisted
+            .entry(entry.reason.to_string())
+            .or_default() += 1;
+    }
+    report
+}
+
+/// Time of payment or default of Agreement and reason of default. Agreements, which
+/// weren't acted upon by plugin, are skipped.
+fn event(
+    outcome: &AgreementOutcome,
+    cfg: &BlacklistReputationsConfig,
+) -> Option<(DateTime<Utc>, Option<BlacklistReason>)> {
+    let terminated = outcome.terminated?;
+    if let Some(rejected) = outcome.rejected {
+        return Some((rejected, Some(BlacklistReason::InvoiceRejected)));
+    }
+    // Without payment timer, payment is counted whenever it comes.
+    if !cfg.enable_timeout_blacklist {
+        return outcome.paid.map(|paid| (paid, None));
+    }
+
+    let timeout = cfg.payment_timeout_with_policy(&outcome.node, outcome.policy.as_deref())
+        + cfg.payment_grace.unwrap_or_default();
+    let deadline = chrono::Duration::from_std(timeout)
+        .map(|timeout| terminated + timeout)
+        .unwrap_or(DateTime::<Utc>::MAX_UTC);
+    match outcome.paid {
+        Some(paid) if paid <= deadline => Some((paid, None)),
+        _ => Some((deadline, Some(BlacklistReason::PaymentTimeout))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn outcome(
+        id: &str,
+        terminated: DateTime<Utc>,
+        paid: Option<DateTime<Utc>>,
+    ) -> AgreementOutcome {
+        AgreementOutcome {
+            id: id.to_string(),
+            node: NodeId::from_str("0xbabe000000000000000000000000000000000000").unwrap(),
+            signed: terminated - chrono::Duration::hours(1),
+            terminated: Some(terminated),
+            paid,
+            rejected: None,
+            amount: None,
+            amount_paid: None,
+            policy: None,
+        }
+    }
+
+    #[test]
+    fn test_ban_expires_during_replay() {
+        let start = DateTime::parse_from_rfc3339("2020-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let cfg = BlacklistReputationsConfig {
+            blacklist_ttl: Some(std::time::Duration::from_secs(3600)),
+            ..Default::default()
+        };
+
+        let history = vec![outcome("unpaid", start, None)];
+        assert_eq!(simulate(&history, &cfg).total_blacklisted(), 1);
+
+        // Ban from the first Agreement expired before the second one was paid.
+        let later = start + chrono::Duration::days(1);
+        let history = vec![
+            outcome("unpaid", start, None),
+            outcome("paid", later, Some(later)),
+        ];
+        assert_eq!(simulate(&history, &cfg).total_blacklisted(), 0);
+    }
+
+    #[test]
+    fn test_late_payment_counted_without_timeout_blacklist() {
+        let start = DateTime::parse_from_rfc3339("2020-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let cfg = BlacklistReputationsConfig {
+            enable_timeout_blacklist: false,
+            ..Default::default()
+        };
+
+        let history = vec![outcome(
+            "late",
+            start,
+            Some(start + chrono::Duration::days(1)),
+        )];
+        let report = simulate(&history, &cfg);
+        assert_eq!(report.agreements, 1);
+        assert!(report.strikes.is_empty());
+        assert_eq!(report.total_blacklisted(), 0);
+    }
+}
@@ -0,0 +1,202 @@
+//! SQLite implementation of `StateBackend` for providers with large blacklists.
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::str::FromStr;
+
+use ya_client_model::NodeId;
+
+use crate::blacklist::{BlacklistEntry, TrackedAgreement};
+use crate::storage::StateBackend;
+
+pub struct SqliteBackend {
+    connection: Connection,
+}
+
+impl SqliteBackend {
+    pub fn open(path: &Path) -> anyhow::Result<SqliteBackend> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS blacklist (
+                node TEXT PRIMARY KEY NOT NULL,
+                reason TEXT NOT NULL,
+                added_at TEXT NOT NULL,
+                agreement_id TEXT,
+                policy TEXT,
+                endpoint TEXT
+            );
+            CREATE TABLE IF NOT EXISTS agreements (
+                id TEXT PRIMARY KEY NOT NULL,
+                node TEXT NOT NULL,
+                signed TEXT NOT NULL,
+                terminated TEXT,
+                pricing TEXT,
+                policy TEXT,
+                payment_timeout TEXT,
+                endpoint TEXT
+            );
+            CREATE INDEX IF NOT EXISTS blacklist_node_idx ON blacklist (node);
+            CREATE INDEX IF NOT EXISTS agreements_node_idx ON agreements (node);",
+        )?;
+
+        // Databases created by older versions miss some columns.
+        add_column(&connection, "blacklist", "agreement_id")?;
+        add_column(&connection, "blacklist", "policy")?;
+        add_column(&connection, "agreements", "policy")?;
+        add_column(&connection, "agreements", "payment_timeout")?;
+        add_column(&connection, "blacklist", "endpoint")?;
+        add_column(&connection, "agreements", "endpoint")?;
+        Ok(SqliteBackend { connection })
+    }
+}
+
+/// Adds nullable TEXT column, if table doesn't have it yet.
+fn add_column(connection: &Connection, table: &str, column: &str) -> anyhow::Result<()> {
+    let exists = connection
+        .prepare("SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2")?
+        .exists(params![table, column])?;
+    if !exists {
+        connection.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} TEXT", table, column),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+impl StateBackend for SqliteBackend {
+    fn load_blacklist(&self) -> anyhow::Result<Vec<BlacklistEntry>> {
+        let mut statement = self.connection.prepare(
+            "SELECT node, reason, added_at, agreement_id, policy, endpoint FROM blacklist",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, DateTime<Utc>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+                row.get::<_, Option<String>>(5)?,
+            ))
+        })?;
+
+        rows.map(|row| {
+            let (node, reason, added, agreement_id, policy, endpoint) = row?;
+            Ok(BlacklistEntry {
+                node: NodeId::from_str(&node)?,
+                reason: serde_json::from_str(&reason)?,
+                added,
+                agreement_id,
+                policy,
+                endpoint,
+            })
+        })
+        .collect()
+    }
+
+    fn add_to_blacklist(&self, entry: &BlacklistEntry) -> anyhow::Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO blacklist
+                (node, reason, added_at, agreement_id, policy, endpoint)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                entry.node.to_string(),
+                serde_json::to_string(&entry.reason)?,
+                entry.added,
+                entry.agreement_id,
+                entry.policy,
+                entry.endpoint
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove_from_blacklist(&self, node: &NodeId) -> anyhow::Result<()> {
+        self.connection.execute(
+            "DELETE FROM blacklist WHERE node = ?1",
+            params![node.to_string()],
+        )?;
+        Ok(())
+    }
+
+    fn is_blacklisted(&self, node: &NodeId) -> anyhow::Result<bool> {
+        Ok(self
+            .connection
+            .query_row(
+                "SELECT 1 FROM blacklist WHERE node = ?1",
+                params![node.to_string()],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    fn load_agreements(&self) -> anyhow::Result<Vec<TrackedAgreement>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, node, signed, terminated, pricing, policy, payment_timeout, endpoint
+                FROM agreements",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, DateTime<Utc>>(2)?,
+                row.get::<_, Option<DateTime<Utc>>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+                row.get::<_, Option<String>>(5)?,
+                row.get::<_, Option<String>>(6)?,
+                row.get::<_, Option<String>>(7)?,
+            ))
+        })?;
+
+        rows.map(|row| {
+            let (id, node, signed, terminated, pricing, policy, payment_timeout, endpoint) = row?;
+            let pricing = match pricing {
+                Some(pricing) => Some(serde_json::from_str(&pricing)?),
+                None => None,
+            };
+            let mut agreement = TrackedAgreement::new(id, NodeId::from_str(&node)?, signed);
+            agreement.terminated = terminated;
+            agreement.pricing = pricing;
+            agreement.policy = policy;
+            agreement.payment_timeout = match payment_timeout {
+                Some(timeout) => Some(humantime::parse_duration(&timeout)?),
+                None => None,
+            };
+            agreement.endpoint = endpoint;
+            Ok(agreement)
+        })
+        .collect()
+    }
+
+    fn track_agreement(&self, agreement: &TrackedAgreement) -> anyhow::Result<()> {
+        let pricing = match &agreement.pricing {
+            Some(pricing) => Some(serde_json::to_string(pricing)?),
+            None => None,
+        };
+        self.connection.execute(
+            "INSERT OR REPLACE INTO agreements
+                (id, node, signed, terminated, pricing, policy, payment_timeout, endpoint)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                agreement.id,
+                agreement.node.to_string(),
+                agreement.signed,
+                agreement.terminated,
+                pricing,
+                agreement.policy,
+                agreement
+                    .payment_timeout
+                    .map(|timeout| humantime::format_duration(timeout).to_string()),
+                agreement.endpoint
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove_agreement(&self, id: &str) -> anyhow::Result<()> {
+        self.connection
+            .execute("DELETE FROM agreements WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+}
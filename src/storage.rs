@@ -0,0 +1,1191 @@
+//! Persistence of plugin state in working directory.
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+
+use ya_client_model::NodeId;
+
+use crate::blacklist::{
+    parse_node_id, BlacklistEntry, BlacklistFile, BlacklistState, NodeStats, TrackedAgreement,
+};
+use crate::remote::RemoteCache;
+use crate::simulation::AgreementOutcome;
+
+/// Format of state files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Yaml,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "yaml" => Ok(Format::Yaml),
+            "json" => Ok(Format::Json),
+            _ => Err(anyhow::anyhow!("Unknown persistence format: {}", s)),
+        }
+    }
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Yaml => "yaml",
+            Format::Json => "json",
+        }
+    }
+
+    pub fn serialize<T: Serialize>(&self, value: &T) -> anyhow::Result<String> {
+        Ok(match self {
+            Format::Yaml => serde_yaml::to_string(value)?,
+            Format::Json => serde_json::to_string_pretty(value)?,
+        })
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(&self, content: &str) -> anyhow::Result<T> {
+        Ok(match self {
+            Format::Yaml => serde_yaml::from_str(content)?,
+            Format::Json => serde_json::from_str(content)?,
+        })
+    }
+}
+
+/// Storage of blacklist and tracked Agreements.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Whole state is rewritten to state files in working directory.
+    #[default]
+    File,
+    /// Changes are written to SQLite database one by one. Requires `sqlite` feature.
+    Sqlite,
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "file" => Ok(Backend::File),
+            "sqlite" => Ok(Backend::Sqlite),
+            _ => Err(anyhow::anyhow!("Unknown state backend: {}", s)),
+        }
+    }
+}
+
+/// Handling of invalid entries in blacklist.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnParseError {
+    /// Log and ignore invalid entries, keeping the valid remainder.
+    #[default]
+    Skip,
+    /// Fail loading whole blacklist.
+    Fail,
+}
+
+impl FromStr for OnParseError {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(OnParseError::Skip),
+            "fail" => Ok(OnParseError::Fail),
+            _ => Err(anyhow::anyhow!("Unknown parse error policy: {}", s)),
+        }
+    }
+}
+
+/// Version of `ReputationSnapshot` format written by this plugin version.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Portable bundle of plugin state used to move reputation between hosts.
+/// Pending payment deadlines are restored from termination times of Agreements.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReputationSnapshot {
+    /// Snapshots with version newer than `SNAPSHOT_VERSION` are refused.
+    pub version: u32,
+    pub created: DateTime<Utc>,
+    /// Locally managed entries. Static and remote blacklists are not included.
+    #[serde(default)]
+    pub blacklist: Vec<BlacklistEntry>,
+    #[serde(default)]
+    pub graylist: HashMap<NodeId, DateTime<Utc>>,
+    #[serde(default)]
+    pub stats: HashMap<NodeId, NodeStats>,
+    #[serde(default)]
+    pub strikes: HashMap<NodeId, u32>,
+    #[serde(default)]
+    pub paid_streak: HashMap<NodeId, u32>,
+    #[serde(default)]
+    pub unpaid: HashMap<NodeId, f64>,
+    #[serde(default)]
+    pub notes: HashMap<NodeId, String>,
+    /// Score factors of penalized Nodes.
+    #[serde(default)]
+    pub reputation: HashMap<NodeId, f64>,
+    #[serde(default)]
+    pub abandon_streak: HashMap<NodeId, u32>,
+    #[serde(default)]
+    pub agreements: Vec<TrackedAgreement>,
+}
+
+/// Store updated on each change of state, instead of rewriting whole state files.
+/// Intended for deployments with blacklists too big to be serialized periodically.
+pub trait StateBackend: Send {
+    fn load_blacklist(&self) -> anyhow::Result<Vec<BlacklistEntry>>;
+    fn add_to_blacklist(&self, entry: &BlacklistEntry) -> anyhow::Result<()>;
+    fn remove_from_blacklist(&self, node: &NodeId) -> anyhow::Result<()>;
+    fn is_blacklisted(&self, node: &NodeId) -> anyhow::Result<bool>;
+
+    fn load_agreements(&self) -> anyhow::Result<Vec<TrackedAgreement>>;
+    /// Inserts Agreement or updates already tracked one.
+    fn track_agreement(&self, agreement: &TrackedAgreement) -> anyhow::Result<()>;
+    fn remove_agreement(&self, id: &str) -> anyhow::Result<()>;
+}
+
+/// State files stored in working directory. File names are built from
+/// name, plugin instance name and extension of configured format, for example
+/// `blacklist-strict.json`, unless path of the file was overridden.
+pub struct StateFiles {
+    workdir: PathBuf,
+    format: Format,
+    instance: String,
+    paths: HashMap<String, PathBuf>,
+}
+
+impl StateFiles {
+    pub fn new(workdir: PathBuf, format: Format) -> StateFiles {
+        StateFiles {
+            workdir,
+            format,
+            instance: String::new(),
+            paths: HashMap::new(),
+        }
+    }
+
+    /// Namespaces files with negotiator instance name, so multiple instances
+    /// can share working directory.
+    pub fn with_instance(mut self, instance: &str) -> StateFiles {
+        self.instance = instance.to_string();
+        self
+    }
+
+    /// Name of file with instance name appended.
+    pub fn file_name(&self, name: &str, extension: &str) -> String {
+        if self.instance.is_empty() {
+            format!("{}.{}", name, extension)
+        } else {
+            format!("{}-{}.{}", name, self.instance, extension)
+        }
+    }
+
+    /// Overrides path of file. Relative paths are resolved against working directory,
+    /// absolute paths are used as is.
+    pub fn with_path(mut self, name: &str, path: Option<&Path>) -> StateFiles {
+        if let Some(path) = path {
+            self.paths.insert(name.to_string(), self.workdir.join(path));
+        }
+        self
+    }
+
+    pub fn workdir(&self) -> &Path {
+        &self.workdir
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        match self.paths.get(name) {
+            Some(path) => path.clone(),
+            None => self
+                .workdir
+                .join(self.file_name(name, self.format.extension())),
+        }
+    }
+
+    /// Checks, if state files can be written, by creating and removing probe file
+    /// in working directory and in directories of files with overridden paths.
+    pub fn check_writable(&self) -> anyhow::Result<()> {
+        let mut dirs = vec![self.workdir.clone()];
+        dirs.extend(
+            self.paths
+                .values()
+                .filter_map(|path| path.parent())
+                .map(|dir| dir.to_path_buf()),
+        );
+        dirs.sort();
+        dirs.dedup();
+
+        for dir in dirs {
+            let probe = dir.join(self.file_name(".write-test", "tmp"));
+            fs::write(&probe, b"")
+                .and_then(|_| fs::remove_file(&probe))
+                .map_err(|e| {
+                    anyhow::anyhow!("Directory {} is not writable: {}", dir.display(), e)
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Path of append-only log. Logs are written as JSON lines regardless of `format`.
+    pub fn log_path(&self, name: &str) -> PathBuf {
+        match self.paths.get(name) {
+            Some(path) => path.clone(),
+            None => self.workdir.join(self.file_name(name, "jsonl")),
+        }
+    }
+
+    /// Lock file marking, that blacklist is used by running plugin instance. Lock is placed
+    /// next to blacklist file (`<blacklist>.lock`), so instances sharing overridden
+    /// `blacklist_path` exclude each other.
+    pub fn lock_path(&self) -> PathBuf {
+        let mut path = self.path("blacklist").into_os_string();
+        path.push(".lock");
+        PathBuf::from(path)
+    }
+
+    /// Reads file content. Returns `None` if file can't be read.
+    pub fn read(&self, name: &str) -> Option<String> {
+        fs::read_to_string(self.path(name)).ok()
+    }
+
+    /// Reads file content. Returns `None` if file doesn't exist, other errors are returned.
+    pub fn read_existing(&self, name: &str) -> std::io::Result<Option<String>> {
+        match fs::read_to_string(self.path(name)) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Loads and parses file. Returns `None` if file doesn't exist.
+    pub fn load<T: DeserializeOwned>(&self, name: &str) -> anyhow::Result<Option<T>> {
+        match self.read_existing(name)? {
+            Some(content) => Ok(Some(self.format.deserialize(&content)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes already serialized content atomically.
+    pub fn write(&self, name: &str, content: &str) -> std::io::Result<()> {
+        write_atomic(&self.path(name), content)
+    }
+
+    pub fn save<T: Serialize>(&self, name: &str, value: &T) -> anyhow::Result<()> {
+        let content = self.format.serialize(value)?;
+        Ok(self.write(name, &content)?)
+    }
+}
+
+/// Advisory lock preventing concurrent writers (plugin instances or CLI) from
+/// clobbering each other's state. Lock is released on drop.
+pub struct StateLock {
+    _file: fs::File,
+}
+
+impl StateLock {
+    /// Fails with holder's PID, if lock is already taken.
+    pub fn acquire(path: &Path) -> anyhow::Result<StateLock> {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        if file.try_lock_exclusive().is_err() {
+            let holder = fs::read_to_string(path).unwrap_or_default();
+            anyhow::bail!(
+                "State is locked by another process (PID: {}). Lock file: {}",
+                holder.trim(),
+                path.display()
+            );
+        }
+
+        file.set_len(0)?;
+        file.write_all(std::process::id().to_string().as_bytes())?;
+        file.sync_all()?;
+        Ok(StateLock { _file: file })
+    }
+}
+
+/// Writes file content to temporary file first and renames it afterwards,
+/// so crash during write never leaves truncated file behind. Temporary file
+/// is removed, if write fails.
+pub fn write_atomic(path: &Path, content: &str) -> std::io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let result = fs::File::create(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(content.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, path));
+    if result.is_err() && tmp_path.is_file() {
+        fs::remove_file(&tmp_path).ok();
+    }
+    result
+}
+
+/// Persistence of `BlacklistState`. Store is shared between negotiator and its timer tasks.
+///
+/// The same store can be passed to multiple components (for example to several
+/// `BlacklistReputation` instances with `with_store`), so Node blacklisted by one of them
+/// is rejected by all. Methods are called concurrently from negotiation and from
+/// background tasks of each component, so implementations must synchronize internally.
+/// Each component loads its own state once on start and later only writes it, while
+/// `find_blacklisted` is consulted in each negotiation for Nodes, which aren't blacklisted
+/// in component's own state. Shared stores should therefore keep entries written by
+/// each component separately instead of replacing whole blacklist on `persist`.
+pub trait ReputationStore: Send + Sync {
+    /// Loads state saved by previous run. Missing state results in empty `BlacklistState`.
+    fn load(&self) -> anyhow::Result<BlacklistState>;
+    /// Writes all state, that should survive restart.
+    fn persist(&self, state: &BlacklistState) -> anyhow::Result<()>;
+    /// Writes only blacklist. Called each time blacklist changes, so stores can
+    /// implement it cheaper than `persist`.
+    fn persist_blacklist(&self, state: &BlacklistState) -> anyhow::Result<()> {
+        self.persist(state)
+    }
+    /// Loads remote blacklist fetched by previous run. Stores without cache return `None`,
+    /// so remote blacklist is enforced only after first synchronization.
+    fn load_remote_cache(&self) -> anyhow::Result<Option<RemoteCache>> {
+        Ok(None)
+    }
+    /// Writes remote blacklist. Called only when fetched content has changed.
+    fn persist_remote_cache(&self, _cache: &RemoteCache) -> anyhow::Result<()> {
+        Ok(())
+    }
+    /// Appends outcomes of Agreements to history. Stores without history drop them.
+    fn append_history(&self, _outcomes: &[AgreementOutcome]) -> anyhow::Result<()> {
+        Ok(())
+    }
+    /// Outcomes recorded with `append_history`. For each Agreement only the latest
+    /// outcome is returned, since late payment replaces outcome of payment timeout.
+    fn load_history(&self) -> anyhow::Result<Vec<AgreementOutcome>> {
+        Ok(vec![])
+    }
+    /// Blacklist entry of Node stored by any component sharing the store. Stores
+    /// used by single component don't need to implement it.
+    fn find_blacklisted(&self, _node: &NodeId) -> anyhow::Result<Option<BlacklistEntry>> {
+        Ok(None)
+    }
+}
+
+/// Blacklist as seen on disk. Used to distinguish our own writes from external edits
+/// and to find out which Nodes were added or removed by operator.
+#[derive(Default)]
+struct PersistedBlacklist {
+    content: String,
+    nodes: HashSet<NodeId>,
+    /// `static_blacklist` section, which is written back unchanged.
+    static_items: Option<Vec<serde_json::Value>>,
+}
+
+/// Blacklist file with operator managed `static_blacklist` section.
+#[derive(Serialize)]
+struct BlacklistSections<'a> {
+    static_blacklist: &'a [serde_json::Value],
+    blacklist: &'a [&'a BlacklistEntry],
+}
+
+/// Keeps state in `StateFiles`. With database `Backend` blacklist and tracked Agreements
+/// are stored in database instead and only remaining state goes to files.
+pub struct FileStore {
+    files: StateFiles,
+    backend: Backend,
+    on_parse_error: OnParseError,
+    fail_on_load_error: bool,
+    /// Last blacklist content written to (or read from) disk.
+    persisted: Mutex<PersistedBlacklist>,
+    /// Blacklists of other instances in working directory. `None` if they aren't shared.
+    peers: Option<Mutex<PeerBlacklists>>,
+}
+
+/// Blacklist files of other plugin instances sharing working directory, rescanned
+/// at most once per `PEER_REFRESH_INTERVAL`.
+#[derive(Default)]
+struct PeerBlacklists {
+    refreshed: Option<Instant>,
+    /// Modification time and entries of each file.
+    files: HashMap<PathBuf, (SystemTime, HashMap<NodeId, BlacklistEntry>)>,
+}
+
+const PEER_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+impl FileStore {
+    pub fn new(files: StateFiles, backend: Backend, on_parse_error: OnParseError) -> FileStore {
+        FileStore {
+            files,
+            backend,
+            on_parse_error,
+            fail_on_load_error: false,
+            persisted: Mutex::new(PersistedBlacklist::default()),
+            peers: None,
+        }
+    }
+
+    /// Makes `find_blacklisted` look into blacklist files of other instances
+    /// in the same working directory.
+    pub fn with_shared_blacklist(mut self, shared: bool) -> FileStore {
+        self.peers = shared.then(|| Mutex::new(PeerBlacklists::default()));
+        self
+    }
+
+    /// Fail loading, when blacklist file exists, but can't be read or parsed.
+    /// Otherwise plugin starts with empty blacklist and the file is backed up.
+    pub fn with_fail_on_load_error(mut self, fail: bool) -> FileStore {
+        self.fail_on_load_error = fail;
+        self
+    }
+
+    /// Loads blacklist file. Missing file means empty blacklist.
+    fn load_blacklist(&self) -> anyhow::Result<BlacklistFile> {
+        let content = match self.files.read_existing("blacklist")? {
+            Some(content) => content,
+            None => {
+                log::info!("Blacklist file doesn't exist. Starting with empty blacklist.");
+                return Ok(BlacklistFile::new(vec![]));
+            }
+        };
+
+        let file = BlacklistFile::parse(self.files.format(), &content, self.on_parse_error)?;
+        *lock(&self.persisted) = PersistedBlacklist {
+            content,
+            nodes: file.nodes(),
+            static_items: file.static_items().cloned(),
+        };
+        Ok(file)
+    }
+
+    /// Reads blacklist file sorted by Node. Missing file means empty blacklist.
+    /// Entries from `static_blacklist` section are not included.
+    pub fn read_blacklist(&self) -> anyhow::Result<Vec<BlacklistEntry>> {
+        let (blacklist, _) = self.load_blacklist()?.into_blacklist();
+        let mut entries = blacklist.into_values().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.node.to_string());
+        Ok(entries)
+    }
+
+    /// Writes blacklist file atomically. Entries are sorted by Node, so file content
+    /// doesn't depend on order of insertion.
+    pub fn write_blacklist(&self, entries: &[BlacklistEntry]) -> anyhow::Result<()> {
+        // Stable order keeps file unchanged, when content didn't change, so it can be
+        // tracked in version control without noisy diffs.
+        let mut entries = entries.iter().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| (entry.node.to_string(), entry.added));
+
+        let mut persisted = lock(&self.persisted);
+        let content = match &persisted.static_items {
+            Some(static_items) => self.files.format().serialize(&BlacklistSections {
+                static_blacklist: static_items,
+                blacklist: &entries,
+            })?,
+            None => self.files.format().serialize(&entries)?,
+        };
+        let nodes = entries.iter().map(|entry| entry.node).collect();
+
+        self.files.write("blacklist", &content)?;
+        persisted.content = content;
+        persisted.nodes = nodes;
+        Ok(())
+    }
+
+    /// Reads operator notes about Nodes. Missing file means no notes.
+    pub fn read_notes(&self) -> anyhow::Result<HashMap<NodeId, String>> {
+        Ok(self.files.load("notes")?.unwrap_or_default())
+    }
+
+    pub fn write_notes(&self, notes: &HashMap<NodeId, String>) -> anyhow::Result<()> {
+        self.files.save("notes", &sorted(notes))
+    }
+
+    /// Removes payment history and strikes of all Nodes. Blacklist and graylist are kept.
+    pub fn reset_stats(&self) -> anyhow::Result<()> {
+        self.files
+            .save("stats", &HashMap::<NodeId, NodeStats>::new())?;
+        self.files.save("strikes", &HashMap::<NodeId, u32>::new())?;
+        self.files
+            .save("paid_streak", &HashMap::<NodeId, u32>::new())?;
+        self.files.save("unpaid", &HashMap::<NodeId, f64>::new())?;
+        Ok(())
+    }
+
+    /// Copies blacklist file aside, so it won't be overwritten by empty blacklist.
+    fn backup_blacklist(&self) -> std::io::Result<PathBuf> {
+        let path = self.files.path("blacklist");
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(".corrupted");
+        let backup = PathBuf::from(backup);
+
+        fs::copy(&path, &backup)?;
+        Ok(backup)
+    }
+
+    /// Loads whitelist edited by operator. NodeIds don't have to be in canonical form.
+    fn load_whitelist(&self) -> anyhow::Result<HashSet<NodeId>> {
+        let whitelist: Vec<String> = self.files.load("whitelist")?.unwrap_or_default();
+        Ok(whitelist
+            .iter()
+            .filter_map(|node| {
+                parse_node_id(node)
+                    .map_err(|e| log::warn!("Invalid whitelist entry [{}]: {}", node, e))
+                    .ok()
+            })
+            .collect())
+    }
+
+    pub fn files(&self) -> &StateFiles {
+        &self.files
+    }
+
+    /// Merges externally edited blacklist file with in-memory state. Nodes added or removed
+    /// in the file since our last write are applied, changes made in memory are kept.
+    pub(crate) fn merge_external(&self, state: &mut BlacklistState) -> anyhow::Result<()> {
+        // `write_blacklist` holds the lock while writing, so file read under the lock
+        // always matches `persisted`, if the last write was ours.
+        let mut persisted = lock(&self.persisted);
+        let content = match self.files.read("blacklist") {
+            Some(content) => content,
+            None => {
+                log::debug!("Can't read blacklist after change.");
+                return Ok(());
+            }
+        };
+
+        // Notification about our own write.
+        if content == persisted.content {
+            return Ok(());
+        }
+
+        let file = BlacklistFile::parse(self.files.format(), &content, self.on_parse_error)?;
+        let static_items = file.static_items().cloned();
+        let static_blacklist = file.static_blacklist();
+        let (external, _) = file.into_blacklist();
+        let nodes = external.keys().cloned().collect::<HashSet<_>>();
+
+        if static_blacklist.len() != state.static_blacklist.len() {
+            log::info!(
+                "Static blacklist changed. {} Nodes are banned permanently.",
+                static_blacklist.len()
+            );
+        }
+        state.static_blacklist = static_blacklist;
+
+        for node in persisted.nodes.difference(&nodes) {
+            if state.remove_entry(node).is_some() {
+                log::info!(
+                    "Node [{}] removed from blacklist file. Unblacklisting..",
+                    node
+                );
+                state.forgive_pending(node);
+            }
+        }
+
+        for (node, entry) in external {
+            if !persisted.nodes.contains(&node) {
+                log::info!("Node [{}] added to blacklist file. Blacklisting..", node);
+                state.blacklist.entry(node).or_insert(entry);
+            }
+        }
+
+        *persisted = PersistedBlacklist {
+            content,
+            nodes,
+            static_items,
+        };
+        state.update_blacklist_size();
+        Ok(())
+    }
+
+    /// Rereads blacklist files of other instances, which changed since last scan.
+    fn refresh_peers(&self, peers: &mut PeerBlacklists) {
+        let own = self.files.path("blacklist");
+        let extension = format!(".{}", self.files.format().extension());
+        let entries = match fs::read_dir(self.files.workdir()) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::debug!("Can't list blacklists of other instances: {}", e);
+                return;
+            }
+        };
+
+        let mut files = HashMap::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            let is_blacklist = name == format!("blacklist{}", extension)
+                || (name.starts_with("blacklist-") && name.ends_with(&extension));
+            if !is_blacklist || path == own {
+                continue;
+            }
+            let modified = match entry.metadata().and_then(|meta| meta.modified()) {
+                Ok(modified) => modified,
+                Err(_) => continue,
+            };
+
+            match peers.files.remove(&path) {
+                Some((previous, blacklist)) if previous == modified => {
+                    files.insert(path, (modified, blacklist));
+                }
+                _ => {
+                    let parsed = fs::read_to_string(&path)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|content| {
+                            BlacklistFile::parse(self.files.format(), &content, OnParseError::Skip)
+                        });
+                    match parsed {
+                        Ok(file) => {
+                            let static_blacklist = file.static_blacklist();
+                            let (mut blacklist, _) = file.into_blacklist();
+                            blacklist.extend(static_blacklist);
+                            files.insert(path, (modified, blacklist));
+                        }
+                        Err(e) => log::debug!(
+                            "Can't read blacklist of other instance {}: {}",
+                            path.display(),
+                            e
+                        ),
+                    }
+                }
+            }
+        }
+        peers.files = files;
+    }
+
+    /// Opens database configured as state backend. File backend doesn't need one.
+    fn open_backend(&self) -> anyhow::Result<Option<Box<dyn StateBackend>>> {
+        match self.backend {
+            Backend::File => Ok(None),
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite => {
+                let path = self
+                    .files
+                    .workdir()
+                    .join(self.files.file_name("state", "sqlite"));
+                log::info!("Using SQLite state backend: {}", path.display());
+                Ok(Some(Box::new(crate::sqlite::SqliteBackend::open(&path)?)))
+            }
+            #[cfg(not(feature = "sqlite"))]
+            Backend::Sqlite => Err(anyhow::anyhow!(
+                "SQLite state backend requires plugin compiled with `sqlite` feature."
+            )),
+        }
+    }
+}
+
+impl ReputationStore for FileStore {
+    fn load(&self) -> anyhow::Result<BlacklistState> {
+        let backend = self.open_backend()?;
+
+        let mut static_blacklist = HashMap::new();
+        let (blacklist, duplicates, agreements) = match &backend {
+            Some(backend) => {
+                let (blacklist, duplicates) =
+                    BlacklistFile::new(backend.load_blacklist()?).into_blacklist();
+                let agreements = backend
+                    .load_agreements()?
+                    .into_iter()
+                    .map(|record| (record.id.clone(), record))
+                    .collect();
+                (blacklist, duplicates, agreements)
+            }
+            None => {
+                let (blacklist, duplicates) = match self.load_blacklist() {
+                    Ok(file) => {
+                        static_blacklist = file.static_blacklist();
+                        file.into_blacklist()
+                    }
+                    Err(e) if self.fail_on_load_error => {
+                        return Err(e.context("Failed to load blacklist"));
+                    }
+                    Err(e) => {
+                        log::error!(
+                            "Failed to load blacklist: {}. BLACKLIST IS EMPTY, NO NODES WILL BE REJECTED.",
+                            e
+                        );
+                        match self.backup_blacklist() {
+                            Ok(backup) => {
+                                log::error!("Blacklist file backed up to {}.", backup.display())
+                            }
+                            Err(e) => log::error!("Failed to back up blacklist file: {}", e),
+                        }
+                        (HashMap::new(), 0)
+                    }
+                };
+                let agreements = self.files.load("agreements")?.unwrap_or_default();
+                (blacklist, duplicates, agreements)
+            }
+        };
+
+        let state = BlacklistState {
+            blacklist,
+            static_blacklist,
+            agreements,
+            strikes: self.files.load("strikes")?.unwrap_or_default(),
+            paid_streak: self.files.load("paid_streak")?.unwrap_or_default(),
+            graylist: self.files.load("graylist")?.unwrap_or_default(),
+            stats: self.files.load("stats")?.unwrap_or_default(),
+            reputation: self.files.load("reputation")?.unwrap_or_default(),
+            unpaid: self.files.load("unpaid")?.unwrap_or_default(),
+            notes: self.read_notes()?,
+            whitelist: self.load_whitelist()?,
+            backend,
+            ..Default::default()
+        };
+        state.update_blacklist_size();
+
+        if duplicates > 0 {
+            log::info!(
+                "Removed {} duplicated blacklist entries. Rewriting blacklist..",
+                duplicates
+            );
+            self.persist_blacklist(&state)?;
+        }
+        Ok(state)
+    }
+
+    fn persist(&self, state: &BlacklistState) -> anyhow::Result<()> {
+        self.persist_blacklist(state)?;
+
+        self.files.save("strikes", &sorted(&state.strikes))?;
+        self.files
+            .save("paid_streak", &sorted(&state.paid_streak))?;
+        self.files.save("graylist", &sorted(&state.graylist))?;
+        self.files.save("stats", &sorted(&state.stats))?;
+        self.files.save("reputation", &sorted(&state.reputation))?;
+        self.files.save("unpaid", &sorted(&state.unpaid))?;
+        if state.backend.is_none() {
+            self.files.save("agreements", &sorted(&state.agreements))?;
+        }
+        Ok(())
+    }
+
+    fn persist_blacklist(&self, state: &BlacklistState) -> anyhow::Result<()> {
+        // Notes are edited together with blacklist, but they are never stored in database.
+        self.write_notes(&state.notes)?;
+
+        // Backend stores each change on it's own.
+        if state.backend.is_some() {
+            return Ok(());
+        }
+
+        // Entries fetched from remote source are not stored locally.
+        let entries = state
+            .blacklist
+            .values()
+            .filter(|entry| !state.remote.contains(&entry.node))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.write_blacklist(&entries)
+    }
+
+    fn load_remote_cache(&self) -> anyhow::Result<Option<RemoteCache>> {
+        self.files.load("remote_cache")
+    }
+
+    fn persist_remote_cache(&self, cache: &RemoteCache) -> anyhow::Result<()> {
+        self.files.save("remote_cache", cache)
+    }
+
+    /// History is append-only, so it isn't rewritten on each flush.
+    fn append_history(&self, outcomes: &[AgreementOutcome]) -> anyhow::Result<()> {
+        let mut content = String::new();
+        for outcome in outcomes {
+            content.push_str(&serde_json::to_string(outcome)?);
+            content.push('\n');
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.files.log_path("history"))?;
+        file.write_all(content.as_bytes())?;
+        Ok(())
+    }
+
+    fn load_history(&self) -> anyhow::Result<Vec<AgreementOutcome>> {
+        let path = self.files.log_path("history");
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut outcomes = Vec::new();
+        for (idx, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<AgreementOutcome>(line) {
+                Ok(outcome) => outcomes.push(outcome),
+                Err(e) => match self.on_parse_error {
+                    OnParseError::Fail => {
+                        anyhow::bail!(
+                            "Invalid history line {} in {}: {}",
+                            idx + 1,
+                            path.display(),
+                            e
+                        )
+                    }
+                    OnParseError::Skip => {
+                        log::warn!("Skipping invalid history line {}: {}", idx + 1, e)
+                    }
+                },
+            }
+        }
+        Ok(latest_outcomes(outcomes))
+    }
+
+    /// Looks into blacklist files of other instances, if enabled by `with_shared_blacklist`.
+    /// Files are rescanned at most every `PEER_REFRESH_INTERVAL`, so new bans of other
+    /// instances are visible with that delay.
+    fn find_blacklisted(&self, node: &NodeId) -> anyhow::Result<Option<BlacklistEntry>> {
+        let mut peers = match &self.peers {
+            Some(peers) => lock(peers),
+            None => return Ok(None),
+        };
+        let outdated = peers
+            .refreshed
+            .map(|refreshed| refreshed.elapsed() >= PEER_REFRESH_INTERVAL)
+            .unwrap_or(true);
+        if outdated {
+            self.refresh_peers(&mut peers);
+            peers.refreshed = Some(Instant::now());
+        }
+        Ok(peers
+            .files
+            .values()
+            .find_map(|(_, blacklist)| blacklist.get(node).cloned()))
+    }
+}
+
+/// Keeps state in memory only. Useful in tests and in applications,
+/// which don't need state to survive restart.
+#[derive(Default)]
+pub struct MemoryStore {
+    snapshot: Arc<Mutex<MemorySnapshot>>,
+    /// Snapshots of all stores created with `share`, including this one.
+    group: Arc<Mutex<Vec<Arc<Mutex<MemorySnapshot>>>>>,
+}
+
+#[derive(Default)]
+struct MemorySnapshot {
+    blacklist: HashMap<NodeId, BlacklistEntry>,
+    agreements: HashMap<String, TrackedAgreement>,
+    strikes: HashMap<NodeId, u32>,
+    paid_streak: HashMap<NodeId, u32>,
+    graylist: HashMap<NodeId, DateTime<Utc>>,
+    stats: HashMap<NodeId, NodeStats>,
+    reputation: HashMap<NodeId, f64>,
+    unpaid: HashMap<NodeId, f64>,
+    notes: HashMap<NodeId, String>,
+    whitelist: HashSet<NodeId>,
+    history: Vec<AgreementOutcome>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    /// Store with initial whitelist, since whitelist is never persisted by plugin.
+    pub fn with_whitelist(whitelist: Vec<NodeId>) -> MemoryStore {
+        let store = MemoryStore::default();
+        lock(&store.snapshot).whitelist = whitelist.into_iter().collect();
+        store
+    }
+
+    /// Blacklist from last `persist` call.
+    pub fn blacklist(&self) -> Vec<BlacklistEntry> {
+        lock(&self.snapshot).blacklist.values().cloned().collect()
+    }
+
+    /// Creates store for another component. Components keep their own state,
+    /// but Nodes blacklisted by any of them are found by `find_blacklisted` of all stores.
+    pub fn share(&self) -> MemoryStore {
+        let mut group = lock(&self.group);
+        if group.is_empty() {
+            group.push(self.snapshot.clone());
+        }
+        let snapshot = Arc::new(Mutex::new(MemorySnapshot {
+            whitelist: lock(&self.snapshot).whitelist.clone(),
+            ..Default::default()
+        }));
+        group.push(snapshot.clone());
+        MemoryStore {
+            snapshot,
+            group: self.group.clone(),
+        }
+    }
+}
+
+impl ReputationStore for MemoryStore {
+    fn load(&self) -> anyhow::Result<BlacklistState> {
+        let snapshot = lock(&self.snapshot);
+        let state = BlacklistState {
+            blacklist: snapshot.blacklist.clone(),
+            agreements: snapshot.agreements.clone(),
+            strikes: snapshot.strikes.clone(),
+            paid_streak: snapshot.paid_streak.clone(),
+            graylist: snapshot.graylist.clone(),
+            stats: snapshot.stats.clone(),
+            reputation: snapshot.reputation.clone(),
+            unpaid: snapshot.unpaid.clone(),
+            notes: snapshot.notes.clone(),
+            whitelist: snapshot.whitelist.clone(),
+            ..Default::default()
+        };
+        state.update_blacklist_size();
+        Ok(state)
+    }
+
+    fn persist(&self, state: &BlacklistState) -> anyhow::Result<()> {
+        let mut snapshot = lock(&self.snapshot);
+        snapshot.blacklist = state.blacklist.clone();
+        snapshot.agreements = state.agreements.clone();
+        snapshot.strikes = state.strikes.clone();
+        snapshot.paid_streak = state.paid_streak.clone();
+        snapshot.graylist = state.graylist.clone();
+        snapshot.stats = state.stats.clone();
+        snapshot.reputation = state.reputation.clone();
+        snapshot.unpaid = state.unpaid.clone();
+        snapshot.notes = state.notes.clone();
+        Ok(())
+    }
+
+    fn append_history(&self, outcomes: &[AgreementOutcome]) -> anyhow::Result<()> {
+        lock(&self.snapshot).history.extend_from_slice(outcomes);
+        Ok(())
+    }
+
+    fn load_history(&self) -> anyhow::Result<Vec<AgreementOutcome>> {
+        Ok(latest_outcomes(lock(&self.snapshot).history.clone()))
+    }
+
+    fn find_blacklisted(&self, node: &NodeId) -> anyhow::Result<Option<BlacklistEntry>> {
+        // Own blacklist is already known to the component and can be outdated
+        // until next `persist`.
+        Ok(lock(&self.group)
+            .iter()
+            .filter(|snapshot| !Arc::ptr_eq(snapshot, &self.snapshot))
+            .find_map(|snapshot| lock(snapshot).blacklist.get(node).cloned()))
+    }
+}
+
+/// Keeps the latest outcome of each Agreement in order of recording.
+fn latest_outcomes(outcomes: Vec<AgreementOutcome>) -> Vec<AgreementOutcome> {
+    let mut latest = HashMap::new();
+    for (idx, outcome) in outcomes.iter().enumerate() {
+        latest.insert(outcome.id.as_str(), idx);
+    }
+    let keep = latest.into_values().collect::<HashSet<_>>();
+    outcomes
+        .into_iter()
+        .enumerate()
+        .filter(|(idx, _)| keep.contains(idx))
+        .map(|(_, outcome)| outcome)
+        .collect()
+}
+
+/// Orders map by serialized key, so persisted maps don't change order between writes.
+fn sorted<K: ToString, V>(map: &HashMap<K, V>) -> BTreeMap<String, &V> {
+    map.iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect()
+}
+
+/// Locks mutex recovering from poisoning. Panic in one of the handlers
+/// or timer tasks shouldn't bring down whole negotiator.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        log::warn!(
+            "{} mutex poisoned. Recovering..",
+            std::any::type_name::<T>()
+        );
+        poisoned.into_inner()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blacklist::{BlacklistReason, BlacklistState};
+
+    fn node(n: u8) -> NodeId {
+        parse_node_id(&format!("0x{:040x}", n)).unwrap()
+    }
+
+    fn store(workdir: &Path, instance: &str) -> FileStore {
+        let files = StateFiles::new(workdir.to_path_buf(), Format::Yaml).with_instance(instance);
+        FileStore::new(files, Backend::File, OnParseError::Fail)
+    }
+
+    #[test]
+    fn test_find_blacklisted_in_other_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let strict = store(dir.path(), "strict").with_shared_blacklist(true);
+        let lenient = store(dir.path(), "lenient");
+        lenient
+            .write_blacklist(&[BlacklistEntry::new(
+                node(1),
+                BlacklistReason::PaymentTimeout,
+            )])
+            .unwrap();
+
+        let entry = strict.find_blacklisted(&node(1)).unwrap().unwrap();
+        assert_eq!(entry.node, node(1));
+        assert!(strict.find_blacklisted(&node(2)).unwrap().is_none());
+
+        // Own blacklist isn't looked into and sharing is opt-in.
+        assert!(lenient.find_blacklisted(&node(1)).unwrap().is_none());
+        let lenient = lenient.with_shared_blacklist(true);
+        assert!(lenient.find_blacklisted(&node(1)).unwrap().is_none());
+    }
+
+    /// State with given entries inserted in given order.
+    fn state<'a>(entries: impl Iterator<Item = (u32, &'a BlacklistEntry)>) -> BlacklistState {
+        let mut state = BlacklistState::default();
+        for (n, entry) in entries {
+            state.blacklist.insert(entry.node, entry.clone());
+            state.strikes.insert(entry.node, n);
+            state.stats.insert(entry.node, NodeStats::default());
+            state.notes.insert(entry.node, format!("note {}", n));
+        }
+        state
+    }
+
+    fn read_files(dir: &Path) -> BTreeMap<String, Vec<u8>> {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| !path.to_string_lossy().ends_with(".lock"))
+            .map(|path| {
+                let name = path.file_name().unwrap().to_string_lossy().to_string();
+                (name, std::fs::read(&path).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_persist_independent_of_insertion_order() {
+        let entries = (1..=50)
+            .map(|n| {
+                (
+                    n as u32,
+                    BlacklistEntry::new(node(n), BlacklistReason::PaymentTimeout),
+                )
+            })
+            .collect::<Vec<_>>();
+        let ordered = || entries.iter().map(|(n, entry)| (*n, entry));
+
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        store(first.path(), "").persist(&state(ordered())).unwrap();
+        store(second.path(), "")
+            .persist(&state(ordered().rev()))
+            .unwrap();
+
+        let files = read_files(first.path());
+        assert!(files.keys().any(|name| name.starts_with("blacklist")));
+        assert_eq!(files, read_files(second.path()));
+
+        store(first.path(), "")
+            .persist(&state(ordered().rev()))
+            .unwrap();
+        assert_eq!(files, read_files(first.path()));
+    }
+
+    #[test]
+    fn test_write_atomic_after_interrupted_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blacklist.yaml");
+        let tmp_path = dir.path().join("blacklist.yaml.tmp");
+        write_atomic(&path, "old").unwrap();
+
+        // Crash in the middle of write leaves only partial temporary file.
+        std::fs::write(&tmp_path, "ne").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old");
+
+        write_atomic(&path, "new").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert!(!tmp_path.exists());
+    }
+
+    #[test]
+    fn test_write_atomic_removes_temporary_file_on_error() {
+        let dir = tempfile::tempdir().unwrap();
+        // Rename fails, because directory can't be replaced by file.
+        let path = dir.path().join("blacklist.yaml");
+        std::fs::create_dir(&path).unwrap();
+        std::fs::write(path.join("entry"), "old").unwrap();
+
+        assert!(write_atomic(&path, "new").is_err());
+        assert!(!dir.path().join("blacklist.yaml.tmp").exists());
+        assert_eq!(std::fs::read_to_string(path.join("entry")).unwrap(), "old");
+    }
+
+    #[test]
+    fn test_reputation_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = BlacklistState::default();
+        state.reputation.insert(node(1), 0.25);
+        state.reputation.insert(node(2), 1.5);
+
+        store(dir.path(), "").persist(&state).unwrap();
+        let loaded = store(dir.path(), "").load().unwrap();
+        assert_eq!(loaded.reputation, state.reputation);
+
+        let memory = MemoryStore::new();
+        memory.persist(&state).unwrap();
+        assert_eq!(memory.load().unwrap().reputation, state.reputation);
+    }
+
+    #[test]
+    fn test_history_keeps_latest_outcome() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(dir.path(), "");
+        let outcome = |id: &str, paid: bool| AgreementOutcome {
+            id: id.to_string(),
+            node: node(1),
+            signed: Utc::now(),
+            terminated: Some(Utc::now()),
+            paid: paid.then(Utc::now),
+            rejected: None,
+            amount: None,
+            amount_paid: None,
+            policy: None,
+        };
+
+        assert!(store.load_history().unwrap().is_empty());
+        store
+            .append_history(&[outcome("agreement-1", false), outcome("agreement-2", true)])
+            .unwrap();
+        store
+            .append_history(&[outcome("agreement-1", true)])
+            .unwrap();
+
+        let history = store.load_history().unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].id, "agreement-2");
+        assert!(history[1].paid.is_some());
+        assert!(dir.path().join("history.jsonl").exists());
+    }
+}